@@ -0,0 +1,99 @@
+use std::env;
+use std::time::Instant;
+
+use batch::Batch;
+use consts::*;
+use game::*;
+use prng::Prng;
+use vec2::Vec2;
+
+const RUNS: usize = 7;
+
+// Usage: bench [games] [turns] [seed]
+// Needs the prng feature as well. Plays the same random games one at a time with
+// TurnState::simulate and all at once with the batch simulator, on a single core.
+pub fn main() {
+    let mut args = env::args().skip(1);
+    let games = args.next().map_or(1000, |s| s.parse::<usize>().expect("Invalid game count!"));
+    let turns = args.next().map_or(20, |s| s.parse::<usize>().expect("Invalid turn count!"));
+    let seed = args.next().map_or(0, |s| s.parse::<u64>().expect("Invalid seed!"));
+
+    let mut rng = Prng::new(seed);
+    let root = TurnState::generate(rng.next_u64());
+    // Indexed by turn then game, made up front so only the simulation gets timed.
+    let mut my_actions = Vec::with_capacity(turns);
+    let mut enemy_actions = Vec::with_capacity(turns);
+    for _ in 0..turns {
+        my_actions.push((0..games).map(|_| random_actions(&mut rng, &root)).collect::<Vec<_>>());
+        enemy_actions.push((0..games).map(|_| random_actions(&mut rng, &root)).collect::<Vec<_>>());
+    }
+
+    // Alternating so both get the same share of whatever else the machine is doing, and keeping the
+    // fastest run of each since the slower ones mostly measure that.
+    let mut states = Vec::new();
+    let mut batch = Batch::new(&root, 0);
+    let mut simulated = 0;
+    let mut scalar_time = f64::INFINITY;
+    let mut batch_time = f64::INFINITY;
+    for _ in 0..RUNS {
+        scalar_time = scalar_time.min(time(|| {
+            states = vec![root; games];
+            let mut stats = Stats::new();
+            simulated = 0;
+            for t in 0..turns {
+                for (g, state) in states.iter_mut().enumerate() {
+                    if state.winner.is_none() {
+                        state.simulate_with(&my_actions[t][g], &enemy_actions[t][g], &mut stats, &mut NoObserver);
+                        simulated += 1;
+                    }
+                }
+            }
+        }));
+        batch_time = batch_time.min(time(|| {
+            batch = Batch::new(&root, games);
+            for t in 0..turns {
+                if batch.is_over() {
+                    break;
+                }
+                batch.step(&my_actions[t], &enemy_actions[t]);
+            }
+        }));
+    }
+
+    let mismatches = states.iter().zip(batch.states().iter()).filter(|&(s, b)| !same_game(s, b)).count();
+    println!("{} games, {} turns, {} simulated turns", games, turns, simulated);
+    println!("simulate: {:>10.0} turns/s per core", simulated as f64 / scalar_time);
+    println!("batch:    {:>10.0} turns/s per core", simulated as f64 / batch_time);
+    if mismatches > 0 {
+        println!("{} games ended up different with the batch simulator!", mismatches);
+    }
+}
+
+// In seconds.
+fn time<F: FnOnce()>(run: F) -> f64 {
+    let start = Instant::now();
+    run();
+    let elapsed = start.elapsed();
+    elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9
+}
+
+// Mostly moves and throws, with a spell now and then, so the scheduler has real work to do.
+fn random_actions(rng: &mut Prng, root: &TurnState) -> [Action; WIZARD_COUNT] {
+    let mut actions = [Action::Move(Vec2::zero(), 0); WIZARD_COUNT];
+    for action in actions.iter_mut() {
+        *action = match rng.below(10) {
+            0 => {
+                let kind = [SpellType::Flipendo, SpellType::Accio, SpellType::Obliviate, SpellType::Petrificus][rng.below(4) as usize];
+                Action::Spell(kind, rng.range_usize(0, root.entities.len()))
+            },
+            1 | 2 | 3 => Action::Throw(rng.vec2_in_rect(Vec2(BORDER_LEFT_X, BORDER_TOP_Y), Vec2(BORDER_RIGHT_X, BORDER_BOTTOM_Y)), rng.range(0, MAX_THROW_POWER + 1)),
+            _ => Action::Move(rng.vec2_in_rect(Vec2(BORDER_LEFT_X, BORDER_TOP_Y), Vec2(BORDER_RIGHT_X, BORDER_BOTTOM_Y)), rng.range(0, MAX_MOVE_THRUST + 1))
+        };
+    }
+    actions
+}
+
+fn same_game(a: &TurnState, b: &TurnState) -> bool {
+    a.my_score == b.my_score && a.enemy_score == b.enemy_score && a.my_mp == b.my_mp && a.enemy_mp == b.enemy_mp &&
+        a.winner == b.winner && a.round == b.round && a.entities[..] == b.entities[..]
+}
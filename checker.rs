@@ -0,0 +1,175 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::str::FromStr;
+
+use consts::*;
+use config::VERBOSE;
+use game::*;
+use parse::*;
+
+// Usage: checker [replay file] [replay.html]
+//
+// A replay is what player 0 got as input on every turn, each followed by the actions
+// of both players (player 0's wizards first, then player 1's):
+//
+//   0 12         <- score and magic, optional
+//   1 12         <- same for the opponent, optional
+//   13           <- entity count
+//   0 WIZARD 1000 5250 0 0 0
+//   ...
+//   MOVE 8000 3750 150
+//   ...          <- 4 action lines
+pub fn main() {
+    match env::args().nth(1) {
+        Some(path) => check(BufReader::new(File::open(&path).unwrap_or_else(|e| panic!("Could not open {}: {}", path, e))), env::args().nth(2)),
+        None       => {
+            let stdin = io::stdin();
+            check(stdin.lock(), None);
+        }
+    }
+}
+
+fn check<R: BufRead>(input: R, html: Option<String>) {
+    // Reading stops at the first line that isn't valid UTF-8, it gets reported once the checking is done.
+    let read_error = RefCell::new(None);
+    let mut lines = LineReader::skipping_blank_lines(input.lines().take_while(|l| match *l {
+        Ok(_)      => true,
+        Err(ref e) => {
+            *read_error.borrow_mut() = Some(e.to_string());
+            false
+        }
+    }).map(Result::unwrap));
+    let report_read_error = |line_number: usize| if let Some(ref e) = *read_error.borrow() {
+        debug!("Invalid replay, line {}: {}", line_number + 1, e);
+    };
+
+    let mut state = TurnState::new(0);
+    if lines.peek().is_none() {
+        debug!("Empty replay.");
+        report_read_error(lines.line_number());
+        return;
+    }
+    if let Err(e) = state.read_input(&mut lines) {
+        debug!("Invalid replay, {}", e);
+        report_read_error(lines.line_number());
+        return;
+    }
+    let mut history = vec![state];
+
+    let mut first_divergence = None;
+    let mut turns = 0;
+    loop {
+        let mut actions = Vec::with_capacity(TOTAL_WIZARD_COUNT);
+        for _ in 0..TOTAL_WIZARD_COUNT {
+            match lines.next() {
+                Some(line) => match Action::from_str(&line) {
+                    Ok(action) => actions.push(action),
+                    Err(e) => {
+                        debug!("Invalid replay, {}", e.at_line(lines.line_number()));
+                        break;
+                    }
+                },
+                None => break
+            }
+        }
+        if actions.len() < TOTAL_WIZARD_COUNT {
+            break;
+        }
+        let round = state.round;
+        if VERBOSE {
+            debug!("Simulating turn {}.", round);
+        }
+        let mut predicted = state;
+        predicted.simulate(&actions[..WIZARD_COUNT], &actions[WIZARD_COUNT..]);
+        let mut reference = state;
+        reference.simulate_reference(&actions[..WIZARD_COUNT], &actions[WIZARD_COUNT..]);
+        if predicted.entities.iter().zip(reference.entities.iter()).any(|(p, r)| p.pos != r.pos || p.vel != r.vel || p.dead != r.dead) {
+            println!("[{}] the collision scheduler disagrees with the reference simulation", round);
+        }
+        // When the score lines are missing, the simulated values are kept.
+        state.my_score = predicted.my_score;
+        state.my_mp = predicted.my_mp;
+        state.enemy_score = predicted.enemy_score;
+        state.enemy_mp = predicted.enemy_mp;
+        if predicted.winner.is_some() || lines.peek().is_none() {
+            break;
+        }
+        if let Err(e) = state.read_input(&mut lines) {
+            debug!("Invalid replay, {}", e);
+            break;
+        }
+        turns += 1;
+        history.push(state);
+        if !compare(round, &predicted, &state) && first_divergence.is_none() {
+            first_divergence = Some(round);
+        }
+        // Things we can't see in the input, so trust the simulation for them.
+        for i in 0..TOTAL_WIZARD_COUNT {
+            state.entities[i].snaffle_timeout = predicted.entities[i].snaffle_timeout;
+        }
+        for bid in state.bludger_ids() {
+            state.entities[bid].linked = predicted.entities[bid].linked;
+        }
+        state.active_spells = predicted.active_spells;
+        state.round = predicted.round;
+    }
+
+    report_read_error(lines.line_number());
+    match first_divergence {
+        Some(round) => println!("Checked {} turns, first divergence on turn {}.", turns, round),
+        None        => println!("Checked {} turns, no divergence.", turns)
+    }
+
+    if let Some(path) = html {
+        save_replay(&path, &history);
+    }
+}
+
+#[cfg(feature = "render")]
+fn save_replay(path: &str, history: &[TurnState]) {
+    let mut file = File::create(path).unwrap_or_else(|e| panic!("Could not create {}: {}", path, e));
+    ::render::write_html_replay(&mut file, history).unwrap();
+}
+
+#[cfg(not(feature = "render"))]
+fn save_replay(_: &str, _: &[TurnState]) {
+    debug!("Saving replays needs the render feature.");
+}
+
+// Prints the differences between the simulated and observed state, returns whether they're the same.
+fn compare(round: usize, predicted: &TurnState, observed: &TurnState) -> bool {
+    let mut same = true;
+    let mut max_pos_err: f64 = 0.;
+    let mut max_vel_err: f64 = 0.;
+    for (p, o) in predicted.entities.iter().zip(observed.entities.iter()) {
+        if p.dead != o.dead {
+            println!("[{}] entity {} ({:?}): predicted dead = {}, observed dead = {}", round, p.id, p.kind, p.dead, o.dead);
+            same = false;
+            continue;
+        }
+        if o.dead {
+            continue;
+        }
+        let pos_err = p.pos.distance_to(o.pos);
+        let vel_err = p.vel.distance_to(o.vel);
+        if pos_err > 0. || vel_err > 0. {
+            println!("[{}] entity {} ({:?}): pos {:?} vs {:?} (err {:.1}), vel {:?} vs {:?} (err {:.1})",
+                     round, p.id, p.kind, p.pos, o.pos, pos_err, p.vel, o.vel, vel_err);
+            same = false;
+        }
+        max_pos_err = max_pos_err.max(pos_err);
+        max_vel_err = max_vel_err.max(vel_err);
+    }
+    if predicted.my_score != observed.my_score || predicted.enemy_score != observed.enemy_score {
+        println!("[{}] score: predicted {} - {}, observed {} - {}", round, predicted.my_score, predicted.enemy_score, observed.my_score, observed.enemy_score);
+        same = false;
+    }
+    if !same {
+        println!("[{}] max position error {:.1}, max velocity error {:.1}", round, max_pos_err, max_vel_err);
+    }
+    same
+}
@@ -0,0 +1,159 @@
+use std::env;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use consts::*;
+use game::*;
+use parse::*;
+
+// Usage: local <bot 0> <bot 1> [seed] [replay.html]
+// Needs the prng feature as well, and render for saving replays.
+pub fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        debug!("Usage: {} <bot 0> <bot 1> [seed] [replay.html]", args[0]);
+        return;
+    }
+    let seed = match args.get(3) {
+        Some(s) => s.parse::<u64>().expect("Invalid seed!"),
+        None    => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            now.as_secs() ^ now.subsec_nanos() as u64
+        }
+    };
+
+    let mut bots = [Bot::spawn(&args[1]), Bot::spawn(&args[2])];
+
+    let mut state = TurnState::generate(seed);
+    let mut history = vec![state];
+
+    for (pid, bot) in bots.iter_mut().enumerate() {
+        bot.send(format!("{}\n", pid).as_bytes());
+    }
+
+    let mut forfeit = None;
+    while state.winner.is_none() {
+        let mut actions = [Vec::new(), Vec::new()];
+        for pid in 0..2 {
+            let mut input = Vec::new();
+            state.write_input(&mut input, pid).unwrap();
+            bots[pid].send(&input);
+            match bots[pid].read_actions() {
+                Ok(acts) => actions[pid] = acts,
+                Err(e) => {
+                    println!("{} forfeits on round {}, {}", bots[pid].name, state.round, e);
+                    forfeit = Some(pid);
+                    break;
+                }
+            }
+        }
+        if forfeit.is_some() {
+            break;
+        }
+        let round = state.round;
+        let stats = state.simulate(&actions[0], &actions[1]);
+        for &(wid, action, fix) in &stats.invalid_actions {
+            let pid = if state.entities[wid].aligned_with(Faction::Me) { 0 } else { 1 };
+            let outcome = match fix {
+                ActionFix::Clamped(clamped) => format!("clamped to {}", clamped.to_string()),
+                ActionFix::Rejected         => "rejected".to_string()
+            };
+            println!("{} did an invalid action on round {}: {}, {}", bots[pid].name, round, action.to_string(), outcome);
+        }
+        if stats.collisions_abandoned {
+            println!("Gave up on collisions on round {} after {} steps.", round, MAX_COLLISION_STEPS);
+        }
+        history.push(state);
+    }
+
+    let winner = match (forfeit, state.winner) {
+        (Some(0), _)                     => Some(1),
+        (Some(_), _)                     => Some(0),
+        (None, Some(Faction::Me))        => Some(0),
+        (None, Some(Faction::Enemy))     => Some(1),
+        (None, _)                        => None
+    };
+    match winner {
+        Some(pid) => println!("Winner: {} ({})", pid, bots[pid].name),
+        None      => println!("Draw")
+    }
+    println!("Score: {} - {}", state.my_score, state.enemy_score);
+    println!("Rounds: {}", state.round);
+    println!("Seed: {}", seed);
+
+    if let Some(path) = args.get(4) {
+        save_replay(path, &history);
+    }
+}
+
+#[cfg(feature = "render")]
+fn save_replay(path: &str, history: &[TurnState]) {
+    use std::fs::File;
+    let mut file = File::create(path).unwrap_or_else(|e| panic!("Could not create {}: {}", path, e));
+    ::render::write_html_replay(&mut file, history).unwrap();
+}
+
+#[cfg(not(feature = "render"))]
+fn save_replay(_: &str, _: &[TurnState]) {
+    debug!("Saving replays needs the render feature.");
+}
+
+struct Bot {
+    name: String,
+    process: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    line_number: usize
+}
+
+impl Bot {
+    fn spawn(path: &str) -> Bot {
+        let mut process = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| panic!("Could not start {}: {}", path, e));
+        let input = process.stdin.take().unwrap();
+        let output = BufReader::new(process.stdout.take().unwrap());
+        Bot {
+            name: path.to_string(),
+            process: process,
+            input: input,
+            output: output,
+            line_number: 0
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        // A bot that died will fail to answer anyway, so write errors don't matter here.
+        let _ = self.input.write_all(data).and_then(|_| self.input.flush());
+    }
+
+    fn read_actions(&mut self) -> Result<Vec<Action>, ParseError> {
+        let mut actions = Vec::with_capacity(WIZARD_COUNT);
+        let mut line = String::new();
+        while actions.len() < WIZARD_COUNT {
+            line.clear();
+            match self.output.read_line(&mut line) {
+                Ok(0) | Err(_) => return Err(ParseError::eof(self.line_number + 1)),
+                Ok(_) => self.line_number += 1
+            }
+            let line = line.trim();
+            // Skip the drawing commands, they're not meant for us.
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            actions.push(Action::from_str(line).map_err(|e| e.at_line(self.line_number))?);
+        }
+        Ok(actions)
+    }
+}
+
+impl Drop for Bot {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+    }
+}
@@ -6,32 +6,54 @@ macro_rules! debug {
 }
 macro_rules! parse_line {
     ($lines:expr, ($($t:ty),*)) => {{
-        let line = $lines.next().unwrap();
-        let mut iter = line.split(" ");
-        (
-            $(iter.next().unwrap().parse::<$t>().unwrap(),)*
-        )
+        match $lines.next() {
+            None => Err(::parse::ParseError::eof($lines.line_number() + 1)),
+            Some(line) => {
+                let line_number = $lines.line_number();
+                let mut iter = line.split_whitespace();
+                let mut index = 0;
+                (|| Ok((
+                    $(::parse::token::<$t, _>(&mut iter, line_number, &mut index, stringify!($t))?,)*
+                )))()
+            }
+        }
     }};
     ($lines:expr, $cons:expr, ($($t:ty),*)) => {{
-        let line = $lines.next().unwrap();
-        let mut iter = line.split(" ");
-        $cons(
-            $(iter.next().unwrap().parse::<$t>().unwrap(),)*
-        )
+        match $lines.next() {
+            None => Err(::parse::ParseError::eof($lines.line_number() + 1)),
+            Some(line) => {
+                let line_number = $lines.line_number();
+                let mut iter = line.split_whitespace();
+                let mut index = 0;
+                (|| Ok($cons(
+                    $(::parse::token::<$t, _>(&mut iter, line_number, &mut index, stringify!($t))?,)*
+                )))()
+            }
+        }
     }};
     ($lines:expr, $t:ty) => {{
-        $lines.next().unwrap().parse::<$t>().unwrap()
+        match $lines.next() {
+            None => Err(::parse::ParseError::eof($lines.line_number() + 1)),
+            Some(line) => {
+                let line_number = $lines.line_number();
+                let mut iter = Some(line.trim()).into_iter();
+                ::parse::token::<$t, _>(&mut iter, line_number, &mut 0, stringify!($t))
+            }
+        }
     }};
 }
 
+#[cfg(feature = "bench")] mod bench;
 #[cfg(feature = "checker")] mod checker;
 #[cfg(feature = "local")] mod local;
-#[cfg(feature = "prng")] mod prng;
+#[cfg(feature = "prng")] #[allow(dead_code)] mod prng;
+#[cfg(feature = "render")] #[allow(dead_code)] mod render;
 #[cfg(feature = "tweaker")] mod tweaker;
 
 #[cfg(feature = "local")] fn main() { local::main(); }
 #[cfg(feature = "checker")] fn main() { checker::main(); }
 #[cfg(feature = "tweaker")] fn main() { tweaker::main(); }
+#[cfg(feature = "bench")] fn main() { bench::main(); }
 
 #[cfg(not(feature = "offline"))] fn main() { cg::main(); }
 
@@ -228,6 +250,7 @@ mod config {
 }
 mod consts {
     use vec2::*;
+    use game::Rounding;
     
     // Game constants
     pub const WIZARD_COUNT: usize = 2;
@@ -255,7 +278,19 @@ mod consts {
     pub const ACCIO_DURATION: usize = 6;
     pub const OBLIVIATE_DURATION: usize = 3;
     pub const PETRIFICUS_DURATION: usize = 1;
+    pub const MAX_MP: i64 = 100;
+    pub const MAX_MOVE_THRUST: i64 = 150;
+    pub const MAX_THROW_POWER: i64 = 500;
+    // What the referee does to them at the end of every turn, after friction.
+    pub const POSITION_ROUNDING: Rounding = Rounding::HalfUp;
+    pub const VELOCITY_ROUNDING: Rounding = Rounding::HalfUp;
+    // Collisions handled in a single turn before movement gives up on them, real games stay far below.
+    pub const MAX_COLLISION_STEPS: usize = 1000;
+    // In field units, way more than the rounding errors of a whole turn could add up to.
+    pub const COLLISION_BOUND_MARGIN: f64 = 1.;
     pub const MAX_ENTITY_COUNT: usize = TOTAL_WIZARD_COUNT + MAX_SNAFFLE_COUNT + BLUDGER_COUNT;
+    // Every wizard casting every round, with each spell sticking around for as long as Accio does.
+    pub const MAX_SPELL_COUNT: usize = TOTAL_WIZARD_COUNT * (ACCIO_DURATION + 1);
     pub const BORDER_TOP_Y: f64 = 0.;
     pub const BORDER_BOTTOM_Y: f64 = 7500.;
     pub const BORDER_LEFT_X: f64 = 0.;
@@ -299,99 +334,257 @@ mod math {
         }
     }
 }
+#[allow(dead_code)] mod parse {
+    use std::fmt;
+    use std::str::FromStr;
+    use std::iter::Peekable;
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct ParseError {
+        pub line: usize,
+        pub token: usize,
+        pub expected: &'static str,
+        pub found: Option<String>
+    }
+
+    impl ParseError {
+        pub fn eof(line: usize) -> ParseError {
+            ParseError {
+                line: line,
+                token: 0,
+                expected: "a line",
+                found: None
+            }
+        }
+
+        // For errors found without knowing where the text came from.
+        pub fn at_line(mut self, line: usize) -> ParseError {
+            self.line = line;
+            self
+        }
+    }
+
+    impl fmt::Display for ParseError {
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+            match self.found {
+                Some(ref found) => write!(fmt, "line {}, token {}: expected {}, found {:?}", self.line, self.token, self.expected, found),
+                None            => write!(fmt, "line {}, token {}: expected {}, found nothing", self.line, self.token, self.expected)
+            }
+        }
+    }
+
+    // Parses the next token, `index` is the position of the token on its line.
+    pub fn token<'a, T: FromStr, I: Iterator<Item = &'a str>>(tokens: &mut I, line: usize, index: &mut usize, expected: &'static str) -> Result<T, ParseError> {
+        let token = tokens.next();
+        let res = match token {
+            Some(text) => text.parse::<T>().map_err(|_| ParseError {
+                line: line,
+                token: *index,
+                expected: expected,
+                found: Some(text.to_string())
+            }),
+            None => Err(ParseError {
+                line: line,
+                token: *index,
+                expected: expected,
+                found: None
+            })
+        };
+        *index += 1;
+        res
+    }
+
+    // Keeps track of the line number so parse_line! can tell where things went wrong.
+    pub struct LineReader<I: Iterator<Item = String>> {
+        lines: Peekable<I>,
+        line_number: usize,
+        skip_blank: bool
+    }
+
+    impl<I: Iterator<Item = String>> LineReader<I> {
+        pub fn new(lines: I) -> LineReader<I> {
+            LineReader {
+                lines: lines.peekable(),
+                line_number: 0,
+                skip_blank: false
+            }
+        }
+
+        // Passes over empty lines, but still counts them so the line numbers match the file.
+        pub fn skipping_blank_lines(lines: I) -> LineReader<I> {
+            LineReader {
+                skip_blank: true,
+                ..LineReader::new(lines)
+            }
+        }
+
+        fn skip_blank_lines(&mut self) {
+            if !self.skip_blank {
+                return;
+            }
+            while self.lines.peek().map_or(false, |l| l.trim().is_empty()) {
+                self.lines.next();
+                self.line_number += 1;
+            }
+        }
+
+        // The number of the line that was read last, starting at 1.
+        #[inline]
+        pub fn line_number(&self) -> usize {
+            self.line_number
+        }
+
+        #[inline]
+        pub fn peek(&mut self) -> Option<&String> {
+            self.skip_blank_lines();
+            self.lines.peek()
+        }
+    }
+
+    impl<I: Iterator<Item = String>> Iterator for LineReader<I> {
+        type Item = String;
+
+        fn next(&mut self) -> Option<String> {
+            self.skip_blank_lines();
+            let line = self.lines.next();
+            if line.is_some() {
+                self.line_number += 1;
+            }
+            line
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use game::{Action, TurnState};
+
+        #[test]
+        fn bad_and_missing_tokens() {
+            let mut lines = LineReader::new("1 2 x 4\n1 2".lines().map(String::from));
+            let bad: Result<(i64, i64, i64, i64), ParseError> = parse_line!(lines, (i64, i64, i64, i64));
+            assert_eq!(bad, Err(ParseError { line: 1, token: 2, expected: "i64", found: Some("x".to_string()) }));
+            let missing: Result<(i64, i64, i64), ParseError> = parse_line!(lines, (i64, i64, i64));
+            assert_eq!(missing, Err(ParseError { line: 2, token: 2, expected: "i64", found: None }));
+        }
+
+        #[test]
+        fn end_of_input() {
+            let mut lines = LineReader::new("3".lines().map(String::from));
+            assert_eq!(parse_line!(lines, usize), Ok(3));
+            let eof = parse_line!(lines, usize);
+            assert_eq!(eof, Err(ParseError::eof(2)));
+            assert_eq!(eof.unwrap_err().to_string(), "line 2, token 0: expected a line, found nothing");
+        }
+
+        #[test]
+        fn unknown_action() {
+            assert_eq!("JUMP 1 2".parse::<Action>(), Err(ParseError { line: 0, token: 0, expected: "an action", found: Some("JUMP".to_string()) }));
+            assert_eq!("MOVE 1 2".parse::<Action>(), Err(ParseError { line: 0, token: 3, expected: "i64", found: None }));
+        }
+
+        // Ids that are all in range don't make up for a count that isn't.
+        #[test]
+        fn too_many_entities() {
+            let mut input = "20\n".to_string();
+            for id in 0..20 {
+                input.push_str(&format!("{} SNAFFLE 1000 1000 0 0 0\n", id % 11));
+            }
+            let mut state = TurnState::new(0);
+            let res = state.read_input(&mut LineReader::new(input.lines().map(String::from)));
+            assert_eq!(res, Err(ParseError { line: 1, token: 0, expected: "an entity count the field has room for", found: Some("20".to_string()) }));
+        }
+    }
+}
 #[cfg(not(feature = "offline"))] mod cg {
     use std::io::prelude::*;
     use std::io;
     
-    use std::borrow::Borrow;
-    
     use std::time::Instant;
     
     use vec2::*;
+    use consts::*;
     use game::*;
     use ai::*;
+    use parse::*;
+    use draw;
     
     pub fn main() {
         let stdin = io::stdin();
-        let mut lines = stdin.lock().lines().map(Result::unwrap);
+        let stdout = io::stdout();
+        run(stdin.lock(), &mut stdout.lock());
+    }
+    
+    // The whole game, reading turns from `input` until it runs out and writing our actions to `output`.
+    pub fn run<R: BufRead, W: Write>(input: R, output: &mut W) {
+        let mut lines = LineReader::new(input.lines().take_while(Result::is_ok).map(Result::unwrap));
         
-        let my_id = parse_line!(lines, PlayerId);
+        if lines.peek().is_none() {
+            return;
+        }
+        let my_id = match parse_line!(lines, PlayerId) {
+            Ok(my_id) => my_id,
+            Err(e) => {
+                debug!("Invalid input, {}", e);
+                return;
+            }
+        };
         
         let mut ai = AIConfig::new();
         
         let mut turn = TurnState::new(my_id);
         
-        let mut sad_wizards_without_snaffles: Vec<(Vec2, EntityId)> = Vec::with_capacity(4);
+        let mut last_snaffles: Vec<(EntityId, Vec2)> = Vec::with_capacity(MAX_SNAFFLE_COUNT);
+        let mut enemy_mp_before_regen = 0;
         
-        loop {
-            let entity_count = parse_line!(lines, usize);
+        while lines.peek().is_some() {
+            let last_turn = turn;
             
-            turn.prepare(entity_count);
+            last_snaffles.clear();
+            if !turn.entities.is_empty() {
+                for sid in turn.snaffle_ids() {
+                    if !turn.entities[sid].dead {
+                        last_snaffles.push((sid, turn.entities[sid].pos));
+                    }
+                }
+            }
             
-            sad_wizards_without_snaffles.clear();
+            let has_magic = match turn.read_input(&mut lines) {
+                Ok(has_magic) => has_magic,
+                Err(e) => {
+                    debug!("Invalid input, {}", e);
+                    return;
+                }
+            };
             
-            for _ in 0..entity_count {
-                let (eid, etype, x, y, vx, vy, state) = parse_line!(lines, (EntityId, String, i64, i64, i64, i64, i64));
-                let pos = Vec2(x as f64, y as f64);
-                let vel = Vec2(vx as f64, vy as f64);
-                match etype.borrow() {
-                    "WIZARD" => {
-                        let wiz = &mut turn.entities[eid];
-                        wiz.pos = pos;
-                        wiz.vel = vel;
-                        wiz.dead = false;
-                        wiz.linked = None;
-                        if state == 1 {
-                            sad_wizards_without_snaffles.push((wiz.pos, wiz.id));
-                        }
-                    },
-                    "OPPONENT_WIZARD" => {
-                        let wiz = &mut turn.entities[eid];
-                        wiz.pos = pos;
-                        wiz.vel = vel;
-                        wiz.dead = false;
-                        wiz.linked = None;
-                        if state == 1 {
-                            sad_wizards_without_snaffles.push((wiz.pos, wiz.id));
-                        }
-                    },
-                    "SNAFFLE" => {
-                        let snaffle = &mut turn.entities[eid];
-                        snaffle.pos = pos;
-                        snaffle.vel = vel;
-                        snaffle.dead = false;
-                    },
-                    "BLUDGER" => {
-                        let bludger = &mut turn.entities[eid];
-                        bludger.pos = pos;
-                        bludger.vel = vel;
-                        bludger.dead = false;
-                    },
-                    _ => unreachable!()
+            if has_magic && turn.round > 0 {
+                if turn.my_mp != last_turn.my_mp {
+                    debug!("Expected to have {} MP, but got {}.", last_turn.my_mp, turn.my_mp);
                 }
+                // Nothing costs 1, so at the cap they can't have spent anything.
+                let enemy_spent = if turn.enemy_mp == MAX_MP { 0 } else { enemy_mp_before_regen + 1 - turn.enemy_mp };
+                track_enemy_spells(&mut turn, &last_turn, enemy_spent);
             }
             
-            // Let's make these sad wizards happy again.
-            // Not having a snaffle is a horrible thing for a wizard.
-            // Especially when they're standing right on one.
-            // We must save the wizards!
-            //
-            // #GiveWizardsTheirSnaffles2k16
-            for sid in turn.snaffle_ids() {
-                for &(pos, wid) in sad_wizards_without_snaffles.iter() {
-                    if turn.entities[sid].pos == pos {
-                        turn.entities[sid].linked = Some(wid);
-                        turn.entities[wid].linked = Some(sid);
+            // Without the score lines, a snaffle that went missing must have gone in the goal it was closest to.
+            if !has_magic {
+                for &(sid, pos) in last_snaffles.iter() {
+                    if turn.entities[sid].dead {
+                        let scorer = if pos.0 > (BORDER_LEFT_X + BORDER_RIGHT_X) / 2. { 0 } else { 1 };
+                        if scorer == turn.my_id { turn.my_score += 1; }
+                                           else { turn.enemy_score += 1; }
                     }
                 }
             }
-
+            
+            turn.expire_spells();
+            
             let start = Instant::now();
             
             let actions = ai.decide(&turn);
             
             // Wish there was a better way to predict future bludger targets…
-            let mut pred_turn = turn.clone();
+            let mut pred_turn = turn;
             pred_turn.invert();
             let enemy_actions = simple_ai(&pred_turn);
             pred_turn.invert();
@@ -402,20 +595,32 @@ mod math {
                 }
             }
 
-            for (_, act) in actions.into_iter().enumerate() {
-                if let Action::Spell(kind, _) = act {
-                    turn.my_mp -= kind.cost();
+            for (sub_id, act) in actions.into_iter().enumerate() {
+                if let Action::Spell(kind, eid) = act {
+                    let wid = turn.my_wizard_ids().start + sub_id;
+                    // Same checks as end_turn, the referee fizzles the rest.
+                    if turn.my_mp >= kind.cost() && turn.check_action(wid, act).is_some() {
+                        turn.my_mp -= kind.cost();
+                        turn.add_spell(kind, wid, eid);
+                    }
                 }
-                println!("{}", act.to_string());
+                writeln!(output, "{}", act.to_string()).unwrap();
             }
+            output.flush().unwrap();
+            // Right away, the bot can get killed before the next frame would have pushed this one out.
+            draw::flush();
             
+            // Our own guess for next turn, only kept if the game doesn't tell us the real values.
+            enemy_mp_before_regen = turn.enemy_mp;
             turn.my_mp += 1;
-            if turn.my_mp > 100 { turn.my_mp = 100; }
+            if turn.my_mp > MAX_MP { turn.my_mp = MAX_MP; }
             turn.enemy_mp += 1;
-            if turn.enemy_mp > 100 { turn.enemy_mp = 100; }
+            if turn.enemy_mp > MAX_MP { turn.enemy_mp = MAX_MP; }
             
-            if turn.my_mp < 0 || turn.enemy_mp < 0 {
-                panic!("MP calculation went seriously wrong!");
+            if !has_magic && (turn.my_mp < 0 || turn.enemy_mp < 0) {
+                debug!("MP calculation went seriously wrong!");
+                turn.my_mp = turn.my_mp.max(0);
+                turn.enemy_mp = turn.enemy_mp.max(0);
             }
             
             let time = start.elapsed().subsec_nanos() / 1_000;
@@ -425,72 +630,278 @@ mod math {
             turn.round += 1;
         }
     }
+    
+    // The magic lines only tell us how much the enemy spent on the turn before, their spells are pieced
+    // back together from that. Obliviate (5) and Petrificus (10) each have a cost of their own. Flipendo
+    // and Accio both cost 20 and leave nothing to find their target from, so they aren't tracked, and
+    // neither are totals that could be two different pairs of spells.
+    fn track_enemy_spells(turn: &mut TurnState, last_turn: &TurnState, spent: i64) {
+        let petrified = petrified_target(turn, last_turn);
+        let (obliviate, petrificus) = match spent {
+            5 | 25 => (true, false),
+            15     => (true, true),
+            30     => (false, true),
+            // Or two Obliviates, unless something just got frozen.
+            10     => (false, petrified.is_some()),
+            _      => (false, false)
+        };
+        // Which of their wizards cast it doesn't matter for these two, only the team does.
+        let caster_ids = turn.enemy_wizard_ids();
+        let round = turn.round - 1;
+        if obliviate {
+            // The bludger closest to one of their wizards is the one they'd want off their backs.
+            let enemy_distance = |bid: EntityId| caster_ids.clone().map(|wid| turn.entities[wid].pos.distance_to(turn.entities[bid].pos))
+                                                                  .fold(f64::INFINITY, f64::min);
+            let target = turn.bludger_ids().filter(|&bid| !turn.is_obliviated(bid, Faction::Enemy))
+                                           .min_by(|&a, &b| enemy_distance(a).partial_cmp(&enemy_distance(b)).unwrap());
+            if let Some(bid) = target {
+                turn.add_spell_cast_in(SpellType::Obliviate, caster_ids.start, bid, round);
+            }
+        }
+        if petrificus {
+            if let Some(id) = petrified {
+                turn.add_spell_cast_in(SpellType::Petrificus, caster_ids.start + 1, id, round);
+            }
+        }
+    }
+    
+    // Velocities are whole numbers and never get rounded down to 0 by friction alone, so something
+    // that was moving and has stopped dead most likely got hit by a Petrificus. Only trusted when
+    // there's exactly one of them.
+    fn petrified_target(turn: &TurnState, last_turn: &TurnState) -> Option<EntityId> {
+        let mut stopped = (0..turn.entities.len()).filter(|&id| {
+            let (e, last) = (&turn.entities[id], &last_turn.entities[id]);
+            !e.dead && !last.dead && !e.aligned_with(Faction::Enemy) && e.vel == Vec2::zero() && last.vel != Vec2::zero()
+        });
+        match (stopped.next(), stopped.next()) {
+            (Some(id), None) => Some(id),
+            _                => None
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn enemy_spells_from_the_mp_they_spent() {
+            let mut last_turn = TurnState::init(TOTAL_WIZARD_COUNT + 1 + BLUDGER_COUNT);
+            let sid = last_turn.snaffle_ids().start;
+            let bids = last_turn.bludger_ids();
+            last_turn.entities[sid].pos = Vec2(8000., 7000.);
+            last_turn.entities[sid].vel = Vec2(300., 0.);
+            last_turn.entities[2].pos = Vec2(9000., 3750.);
+            last_turn.round = 5;
+            let mut turn = last_turn;
+            turn.entities[sid].vel = Vec2::zero();
+            turn.round = 6;
+
+            // Flipendo and Accio or two Petrificus, no telling.
+            let mut unknown = turn;
+            track_enemy_spells(&mut unknown, &last_turn, 20);
+            assert!(unknown.active_spells.is_empty());
+
+            track_enemy_spells(&mut turn, &last_turn, OBLIVIATE_COST + PETRIFICUS_COST);
+            let spells: Vec<_> = turn.active_spells.iter().map(|s| (s.kind, s.target_id, s.casted)).collect();
+            assert_eq!(spells, vec![(SpellType::Petrificus, sid, 5), (SpellType::Obliviate, bids.start + 1, 5)]);
+            assert!(turn.is_obliviated(bids.start + 1, Faction::Enemy));
+        }
+    }
 }
 #[allow(dead_code)] mod draw {
     use vec2::Vec2;
 
     use std::io;
     use std::io::prelude::*;
+    use std::fmt;
+    use std::fmt::Write as FmtWrite;
+    use std::fs::File;
+    use std::mem;
+    use std::cell::RefCell;
+    use std::f64::consts::PI;
     use config;
     
     pub const SCALING: f64 = 0.05;
     pub const ENABLED: bool = config::DRAWING_ENABLED;
     pub const FIELD_OFFSET: Vec2 = Vec2(99.5, 299.5);
+    // The whole picture in field units.
+    pub const VIEW_SIZE: Vec2 = Vec2(811. / SCALING, 405. / SCALING);
 
     // shiny-pancake --size 811x405
 
+    // Anything the field can be drawn on. Positions and sizes are in field units,
+    // the stroke width is in pixels like shiny-pancake has it.
+    pub trait Canvas {
+        fn set_fill_color(&mut self, r: u8, g: u8, b: u8, a: u8);
+        fn set_stroke_color(&mut self, r: u8, g: u8, b: u8, a: u8);
+        fn set_stroke_width(&mut self, width: f64);
+        fn no_fill(&mut self);
+        fn no_stroke(&mut self);
+        fn circle(&mut self, pos: Vec2, radius: f64);
+        fn rect(&mut self, pos: Vec2, size: Vec2);
+        fn ellipse(&mut self, pos: Vec2, size: Vec2);
+        fn line(&mut self, from: Vec2, to: Vec2);
+        // `pos` is the left end of the baseline, `size` the height of the letters. Drawn in the fill color.
+        fn text(&mut self, pos: Vec2, size: f64, text: &str);
+        fn start_frame(&mut self);
+
+        fn polyline(&mut self, points: &[Vec2]) {
+            for pair in points.windows(2) {
+                self.line(pair[0], pair[1]);
+            }
+        }
+
+        // From `from` to `from + dir`, so a velocity can be drawn as is.
+        fn arrow(&mut self, from: Vec2, dir: Vec2) {
+            let len = dir.len();
+            if len == 0. {
+                return;
+            }
+            let to = from + dir;
+            let head = (len * 0.25).min(300.);
+            self.line(from, to);
+            self.line(to, to - dir.rotate(ARROW_HEAD_ANGLE) * (head / len));
+            self.line(to, to - dir.rotate(-ARROW_HEAD_ANGLE) * (head / len));
+        }
+
+        // Dashes of roughly `dash` long with gaps of the same size.
+        fn dashed_circle(&mut self, pos: Vec2, radius: f64, dash: f64) {
+            let count = ((PI * radius / dash).round() as usize).max(4);
+            let step = PI / count as f64;
+            for i in 0..count {
+                let angle = 2. * step * i as f64;
+                self.line(pos + Vec2::from_angle(angle, radius), pos + Vec2::from_angle(angle + step, radius));
+            }
+        }
+    }
+
+    const ARROW_HEAD_ANGLE: f64 = PI / 6.;
+
+    // The shiny-pancake text commands, going to the current sink.
+    pub struct ShinyPancake;
+
+    impl Canvas for ShinyPancake {
+        fn set_fill_color(&mut self, r: u8, g: u8, b: u8, a: u8) { set_fill_color(r, g, b, a); }
+        fn set_stroke_color(&mut self, r: u8, g: u8, b: u8, a: u8) { set_stroke_color(r, g, b, a); }
+        fn set_stroke_width(&mut self, width: f64) { set_stroke_width(width); }
+        fn no_fill(&mut self) { no_fill(); }
+        fn no_stroke(&mut self) { no_stroke(); }
+        fn circle(&mut self, pos: Vec2, radius: f64) { circle(pos, radius); }
+        fn rect(&mut self, pos: Vec2, size: Vec2) { rect(pos, size); }
+        fn ellipse(&mut self, pos: Vec2, size: Vec2) { ellipse(pos, size); }
+        fn line(&mut self, from: Vec2, to: Vec2) { line(from, to); }
+        fn text(&mut self, pos: Vec2, size: f64, text: &str) { self::text(pos, size, text); }
+        fn start_frame(&mut self) { start_frame(); }
+    }
+
+    // Where the drawing commands end up. Never stdout, that's where our actions go.
+    pub enum Sink {
+        Stderr,
+        File(File),
+        Buffer
+    }
+
+    struct Output {
+        sink: Sink,
+        frame: String,
+        frames: Vec<String>
+    }
+
+    thread_local! {
+        static OUTPUT: RefCell<Output> = RefCell::new(Output {
+            sink: Sink::Stderr,
+            frame: String::new(),
+            frames: Vec::new()
+        });
+    }
+
+    fn emit(args: fmt::Arguments) {
+        OUTPUT.with(|o| {
+            let frame = &mut o.borrow_mut().frame;
+            frame.write_fmt(args).unwrap();
+            frame.push('\n');
+        });
+    }
+
     macro_rules! output {
-        () => { io::stdout() }
+        ($($arg:tt)*) => { emit(format_args!($($arg)*)) }
+    }
+
+    // Frames are written in one go, so they don't get mixed up with anything else on the same stream.
+    pub fn flush() {
+        OUTPUT.with(|o| {
+            let o = &mut *o.borrow_mut();
+            if o.frame.is_empty() {
+                return;
+            }
+            let frame = mem::replace(&mut o.frame, String::new());
+            match o.sink {
+                Sink::Stderr          => { io::stderr().write_all(frame.as_bytes()).unwrap(); },
+                Sink::File(ref mut f) => { f.write_all(frame.as_bytes()).unwrap(); },
+                Sink::Buffer          => o.frames.push(frame)
+            }
+        });
+    }
+
+    pub fn set_sink(sink: Sink) {
+        flush();
+        OUTPUT.with(|o| o.borrow_mut().sink = sink);
+    }
+
+    // All frames collected so far by the Buffer sink.
+    pub fn take_frames() -> Vec<String> {
+        flush();
+        OUTPUT.with(|o| mem::replace(&mut o.borrow_mut().frames, Vec::new()))
     }
 
     pub fn set_fill_color(r: u8, g: u8, b: u8, a: u8) {
         if ENABLED {
-            writeln!(output!(), "#FILL_COLOR {:.0} {:.0} {:.0} {:.0}", r, g, b, a).unwrap();
+            output!("#FILL_COLOR {:.0} {:.0} {:.0} {:.0}", r, g, b, a);
         }
     }
 
     pub fn set_stroke_color(r: u8, g: u8, b: u8, a: u8) {
         if ENABLED {
-            writeln!(output!(), "#STROKE_COLOR {:.0} {:.0} {:.0} {:.0}", r, g, b, a).unwrap();
+            output!("#STROKE_COLOR {:.0} {:.0} {:.0} {:.0}", r, g, b, a);
         }
     }
 
     pub fn set_stroke_width(width: f64) {
         if ENABLED {
-            writeln!(output!(), "#STROKE_WIDTH {}", width).unwrap();
+            output!("#STROKE_WIDTH {}", width);
         }
     }
 
     pub fn no_fill() {
         if ENABLED {
-            writeln!(output!(), "#NOFILL").unwrap();
+            output!("#NOFILL");
         }
     }
 
     pub fn no_stroke() {
         if ENABLED {
-            writeln!(output!(), "#NOSTROKE").unwrap();
+            output!("#NOSTROKE");
         }
     }
     
     pub fn circle(mut pos: Vec2, radius: f64) {
         if ENABLED {
             pos += FIELD_OFFSET;
-            writeln!(output!(), "#CIRCLE {:.0} {:.0} {:.0}", pos.0 * SCALING, pos.1 * SCALING, radius * SCALING).unwrap();
+            output!("#CIRCLE {:.0} {:.0} {:.0}", pos.0 * SCALING, pos.1 * SCALING, radius * SCALING);
         }
     }
     
     pub fn rect(mut pos: Vec2, size: Vec2) {
         if ENABLED {
             pos += FIELD_OFFSET;
-            writeln!(output!(), "#RECT {:.0} {:.0} {:.0} {:.0}", pos.0 * SCALING, pos.1 * SCALING, size.0 * SCALING, size.1 * SCALING).unwrap();
+            output!("#RECT {:.0} {:.0} {:.0} {:.0}", pos.0 * SCALING, pos.1 * SCALING, size.0 * SCALING, size.1 * SCALING);
         }
     }
     
     pub fn ellipse(mut pos: Vec2, size: Vec2) {
         if ENABLED {
             pos += FIELD_OFFSET;
-            writeln!(output!(), "#ELLIPSE {:.0} {:.0} {:.0} {:.0}", pos.0 * SCALING, pos.1 * SCALING, size.0 * SCALING, size.1 * SCALING).unwrap();
+            output!("#ELLIPSE {:.0} {:.0} {:.0} {:.0}", pos.0 * SCALING, pos.1 * SCALING, size.0 * SCALING, size.1 * SCALING);
         }
     }
 
@@ -498,13 +909,21 @@ mod math {
         if ENABLED {
             from += FIELD_OFFSET;
             to += FIELD_OFFSET;
-            writeln!(output!(), "#LINE {:.0} {:.0} {:.0} {:.0}", from.0 * SCALING, from.1 * SCALING, to.0 * SCALING, to.1 * SCALING).unwrap();
+            output!("#LINE {:.0} {:.0} {:.0} {:.0}", from.0 * SCALING, from.1 * SCALING, to.0 * SCALING, to.1 * SCALING);
+        }
+    }
+    
+    pub fn text(mut pos: Vec2, size: f64, text: &str) {
+        if ENABLED {
+            pos += FIELD_OFFSET;
+            output!("#TEXT {:.0} {:.0} {:.0} {}", pos.0 * SCALING, pos.1 * SCALING, size * SCALING, text);
         }
     }
     
     pub fn start_frame() {
         if ENABLED {
-            writeln!(output!(), "#FRAME_START").unwrap();
+            flush();
+            output!("#FRAME_START");
         }
     }
 }
@@ -515,13 +934,19 @@ mod math {
     use std::mem;
     use std::f64;
     
+    use std::borrow::Borrow;
+    use std::io;
+    use std::io::prelude::*;
+    
     use std::ops::{Range, Deref, DerefMut};
     
-    use draw;
+    use draw::Canvas;
     use consts::*;
     use config::*;
     use vec2::*;
     use math::*;
+    use parse::*;
+    #[cfg(feature = "prng")] use prng::Prng;
     
     pub type PlayerId = usize;
     pub type EntityId = usize;
@@ -581,13 +1006,52 @@ mod math {
         }
     }
 
-    #[derive(Clone, Debug)]
+    // How the referee turns a value back into a whole number at the end of a turn. Goes through
+    // an integer like it does in Java, so NaN ends up as 0 and -0 as 0.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum Rounding {
+        // Math.round: halves always go up, so -2.5 becomes -2 where f64::round would give -3.
+        HalfUp
+    }
+
+    const TWO_52: f64 = 4503599627370496.;
+    const TWO_63: f64 = 9223372036854775808.;
+
+    impl Rounding {
+        // Only adds, compares and selects, f64::round and the cast to i64 are library calls that keep
+        // the batch simulator from vectorizing it.
+        #[inline]
+        pub fn apply(self, x: f64) -> f64 {
+            match self {
+                Rounding::HalfUp => {
+                    // Past 2^52 there's no fraction left, below that adding it pushes the fraction out of
+                    // the mantissa, which rounds to nearest with the halves going to the even side.
+                    let shift = if x < 0. { -TWO_52 } else { TWO_52 };
+                    let nearest = if x.abs() < TWO_52 { (x + shift) - shift } else { x };
+                    // The halves that went down have to go up.
+                    let rounded = if nearest - x == -0.5 { nearest + 1. } else { nearest };
+                    // Same as going through an i64: NaN is 0, it saturates, and adding 0 turns -0 into 0.
+                    // Kept as plain selects, an else-if chain compiles to branches.
+                    let saturated = if rounded >= TWO_63 { TWO_63 } else { rounded };
+                    let saturated = if saturated <= -TWO_63 { -TWO_63 } else { saturated };
+                    if rounded.is_nan() { 0. } else { saturated + 0. }
+                }
+            }
+        }
+
+        #[inline]
+        pub fn apply_vec(self, v: Vec2) -> Vec2 {
+            Vec2(self.apply(v.0), self.apply(v.1))
+        }
+    }
+
+    #[derive(Copy, Clone, Debug)]
     pub struct Spell {
-        caster_id: EntityId,
-        target_id: EntityId,
-        kind: SpellType,
-        casted: usize,
-        duration: usize
+        pub caster_id: EntityId,
+        pub target_id: EntityId,
+        pub kind: SpellType,
+        pub casted: usize,
+        pub duration: usize
     }
 
     impl Spell {
@@ -602,6 +1066,77 @@ mod math {
         }
     }
     
+    // The active spells, in the order they get applied.
+    #[derive(Copy, Clone)]
+    pub struct SpellList {
+        spells: [Spell; MAX_SPELL_COUNT],
+        len: usize
+    }
+
+    impl Deref for SpellList {
+        type Target = [Spell];
+
+        #[inline]
+        fn deref(&self) -> &[Spell] {
+            &self.spells[..self.len]
+        }
+    }
+
+    impl DerefMut for SpellList {
+        #[inline]
+        fn deref_mut(&mut self) -> &mut [Spell] {
+            &mut self.spells[..self.len]
+        }
+    }
+
+    impl fmt::Debug for SpellList {
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+            fmt.debug_list().entries(self.iter()).finish()
+        }
+    }
+
+    impl SpellList {
+        pub fn new() -> SpellList {
+            SpellList {
+                spells: [Spell::new(SpellType::Petrificus, 0, 0, 0); MAX_SPELL_COUNT],
+                len: 0
+            }
+        }
+
+        pub fn insert(&mut self, index: usize, spell: Spell) {
+            if self.len == MAX_SPELL_COUNT {
+                panic!("Cannot have more than {} active spells!", MAX_SPELL_COUNT);
+            }
+            for i in (index..self.len).rev() {
+                self.spells[i + 1] = self.spells[i];
+            }
+            self.spells[index] = spell;
+            self.len += 1;
+        }
+
+        #[inline]
+        pub fn push(&mut self, spell: Spell) {
+            let len = self.len;
+            self.insert(len, spell);
+        }
+
+        // Keeps the spells for which `keep` is true, in the same order.
+        pub fn retain<F: FnMut(&Spell) -> bool>(&mut self, mut keep: F) {
+            let mut kept = 0;
+            for i in 0..self.len {
+                if keep(&self.spells[i]) {
+                    self.spells[kept] = self.spells[i];
+                    kept += 1;
+                }
+            }
+            self.len = kept;
+        }
+
+        pub fn clear(&mut self) {
+            self.len = 0;
+        }
+    }
+
     #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
     pub enum SpellType {
         Flipendo,
@@ -620,6 +1155,14 @@ mod math {
             }
         }
 
+        pub fn can_target(self, kind: EntityType, friendly: bool) -> bool {
+            match self {
+                SpellType::Obliviate => kind == EntityType::Bludger,
+                SpellType::Accio     => kind != EntityType::Wizard,
+                _                    => !friendly
+            }
+        }
+
         pub fn cost(self) -> i64 {
             match self {
                 SpellType::Flipendo   => FLIPENDO_COST,
@@ -630,12 +1173,124 @@ mod math {
         }
     }
 
-    #[derive(Clone, Debug)]
+    // What check_action did to an action it couldn't take as it was.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum ActionFix {
+        // Thrust or power out of range, this is what got done instead.
+        Clamped(Action),
+        // Nothing got done at all.
+        Rejected
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
     pub struct Stats {
         pub collisions: Vec<(EntityId, EntityId)>,
         pub collected: Vec<(EntityId, EntityId)>,
+        // Wizard, what it asked for and whether that got clamped or thrown away.
+        pub invalid_actions: Vec<(EntityId, Action, ActionFix)>,
+        // Snaffle, who scored and when the snaffle crossed the line, as a time into the turn.
+        pub goals: Vec<(EntityId, Faction, f64)>,
+        pub wall_bounces: usize,
+        pub pole_bounces: usize,
+        // Caster, spell and target.
+        pub spells_cast: Vec<(EntityId, SpellType, EntityId)>,
+        pub spells_fizzled: Vec<(EntityId, SpellType, EntityId)>,
+        // By wizard id.
+        pub bludger_hits: [usize; TOTAL_WIZARD_COUNT],
         pub my_score_gain: i64,
-        pub enemy_score_gain: i64
+        pub enemy_score_gain: i64,
+        // Movement gave up on collisions after MAX_COLLISION_STEPS and let everything fly through each other.
+        pub collisions_abandoned: bool
+    }
+
+    impl Stats {
+        pub fn new() -> Stats {
+            Stats {
+                collisions: Vec::new(),
+                collected: Vec::new(),
+                invalid_actions: Vec::new(),
+                goals: Vec::new(),
+                wall_bounces: 0,
+                pole_bounces: 0,
+                spells_cast: Vec::new(),
+                spells_fizzled: Vec::new(),
+                bludger_hits: [0; TOTAL_WIZARD_COUNT],
+                my_score_gain: 0,
+                enemy_score_gain: 0,
+                collisions_abandoned: false
+            }
+        }
+
+        // Empties everything but keeps the memory around, for reusing the same Stats over a whole search.
+        pub fn clear(&mut self) {
+            self.collisions.clear();
+            self.collected.clear();
+            self.invalid_actions.clear();
+            self.goals.clear();
+            self.wall_bounces = 0;
+            self.pole_bounces = 0;
+            self.spells_cast.clear();
+            self.spells_fizzled.clear();
+            self.bludger_hits = [0; TOTAL_WIZARD_COUNT];
+            self.my_score_gain = 0;
+            self.enemy_score_gain = 0;
+            self.collisions_abandoned = false;
+        }
+    }
+
+    // Gets told about everything that happens in simulate, as it happens. Everything defaults to
+    // doing nothing, so simulating with NoObserver costs the same as not observing at all.
+    #[allow(unused_variables)]
+    pub trait Observer {
+        // Moves, throws and bludgers going after their target.
+        fn thrust(&mut self, id: EntityId, towards: Vec2, power: f64) {}
+        fn spell_effect(&mut self, spell: &Spell) {}
+        // `t` is the time into the turn, `state` is at the moment of impact, before the bounce.
+        fn collision(&mut self, state: &TurnState, t: f64, collision: Collision) {}
+        fn pickup(&mut self, wid: EntityId, sid: EntityId) {}
+        fn goal(&mut self, sid: EntityId, scorer: Faction) {}
+        fn cast(&mut self, wid: EntityId, kind: SpellType, target_id: EntityId) {}
+    }
+
+    pub struct NoObserver;
+
+    impl Observer for NoObserver {}
+
+    // Collects the paths and the collision points for draw_prediction.
+    struct Tracer {
+        turn: usize,
+        paths: Vec<Vec<Vec2>>,
+        marks: Vec<(f64, Collision, Vec2)>
+    }
+
+    impl Observer for Tracer {
+        fn collision(&mut self, state: &TurnState, t: f64, collision: Collision) {
+            let contact = match collision {
+                Collision::Entity(i, j) => {
+                    let (a, b) = (&state.entities[i], &state.entities[j]);
+                    self.paths[i].push(a.pos);
+                    self.paths[j].push(b.pos);
+                    a.pos + (b.pos - a.pos) * (a.kind.radius() / (a.kind.radius() + b.kind.radius()))
+                },
+                Collision::WallRebound(i, is_vertical) => {
+                    let e = &state.entities[i];
+                    self.paths[i].push(e.pos);
+                    match (is_vertical, e.vel.1 < 0., e.vel.0 < 0.) {
+                        (true, true, _)   => Vec2(e.pos.0, BORDER_TOP_Y),
+                        (true, false, _)  => Vec2(e.pos.0, BORDER_BOTTOM_Y),
+                        (false, _, true)  => Vec2(BORDER_LEFT_X, e.pos.1),
+                        (false, _, false) => Vec2(BORDER_RIGHT_X, e.pos.1)
+                    }
+                },
+                Collision::PoleRebound(i, pid) => {
+                    let pos = state.entities[i].pos;
+                    self.paths[i].push(pos);
+                    POLE_POS[pid] + (pos - POLE_POS[pid]).norm() * POLE_RADIUS
+                },
+                Collision::None => return
+            };
+            self.marks.push((self.turn as f64 + t, collision, contact));
+        }
     }
 
     #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -646,7 +1301,7 @@ mod math {
         PoleRebound(EntityId, PoleId)
     }
     
-    #[derive(Clone, Debug)]
+    #[derive(Copy, Clone, Debug)]
     pub struct TurnState {
         pub round: usize,
         pub winner: Option<Faction>,
@@ -655,7 +1310,7 @@ mod math {
         pub enemy_mp: i64,
         pub my_score: i64,
         pub enemy_score: i64,
-        pub active_spells: Vec<Spell>,
+        pub active_spells: SpellList,
         pub my_goal: Goal,
         pub enemy_goal: Goal,
         pub entities: EntityStore
@@ -674,7 +1329,7 @@ mod math {
                 enemy_mp: 0,
                 my_score: 0,
                 enemy_score: 0,
-                active_spells: Vec::new(),
+                active_spells: SpellList::new(),
                 entities: EntityStore::new(my_id),
                 my_goal: Goal::from_id(my_id),
                 enemy_goal: Goal::from_id(enemy_id)
@@ -685,6 +1340,10 @@ mod math {
         pub fn init(entity_count: usize) -> TurnState {
             let mut entities = EntityStore::new(0);
             entities.prepare(entity_count);
+            // prepare leaves everything dead for the input to bring back, a fresh game has it all on the field.
+            for e in entities.iter_mut() {
+                e.dead = false;
+            }
             TurnState {
                 round: 0,
                 winner: None,
@@ -694,31 +1353,66 @@ mod math {
                 my_score: 0,
                 enemy_score: 0,
                 entities: entities,
-                active_spells: Vec::new(),
+                active_spells: SpellList::new(),
                 my_goal: Goal::from_id(0),
                 enemy_goal: Goal::from_id(1)
             }
         }
 
-        #[inline]
-        pub fn from_vecs(wizards: Vec<Entity>, snaffles: Vec<Entity>, bludgers: Vec<Entity>) -> TurnState {
-            let mut inner = Vec::with_capacity(wizards.len() + snaffles.len() + bludgers.len());
-            for wizard  in wizards  { inner.push(wizard);  }
-            for snaffle in snaffles { inner.push(snaffle); }
-            for bludger in bludgers { inner.push(bludger); }
-            TurnState {
-                round: 0,
-                winner: None,
+        // A fresh game like the referee would set it up, 5 or 7 snaffles depending on the seed.
+        #[cfg(feature = "prng")]
+        pub fn generate(seed: u64) -> TurnState {
+            let mut rng = Prng::new(seed);
+            let snaffle_count = if rng.next_bool() { 5 } else { 7 };
+            TurnState::generate_with(&mut rng, snaffle_count)
+        }
+
+        // Snaffles are placed in mirrored pairs around the center, with the odd one right in the middle.
+        #[cfg(feature = "prng")]
+        pub fn generate_with(rng: &mut Prng, snaffle_count: usize) -> TurnState {
+            let mut state = TurnState::init(TOTAL_WIZARD_COUNT + snaffle_count + BLUDGER_COUNT);
+            let center = Vec2((BORDER_LEFT_X + BORDER_RIGHT_X) / 2., GOAL_Y);
+            let ids = state.snaffle_ids();
+            let mut sid = ids.start;
+            if snaffle_count % 2 == 1 {
+                state.entities[sid].pos = center;
+                sid += 1;
+            }
+            let min = Vec2(BORDER_LEFT_X + 2000., BORDER_TOP_Y + 500.);
+            let max = Vec2(center.0 - SNAFFLE_RADIUS, BORDER_BOTTOM_Y - 500.);
+            while sid < ids.end {
+                let pos = rng.vec2_in_rect(min, max).floor();
+                let mirrored = center * 2. - pos;
+                let overlaps = state.entities[..sid].iter().chain(state.bludgers().iter()).any(|e| {
+                    let radius_sum = e.kind.radius() + SNAFFLE_RADIUS;
+                    e.pos.distance_sq(pos) <= radius_sum.powi(2) || e.pos.distance_sq(mirrored) <= radius_sum.powi(2)
+                });
+                if overlaps {
+                    continue;
+                }
+                state.entities[sid].pos = pos;
+                state.entities[sid + 1].pos = mirrored;
+                sid += 2;
+            }
+            state
+        }
+
+        #[inline]
+        pub fn from_vecs(wizards: Vec<Entity>, snaffles: Vec<Entity>, bludgers: Vec<Entity>) -> TurnState {
+            let mut entities = EntityStore::new(0);
+            for wizard  in wizards  { entities.push(wizard);  }
+            for snaffle in snaffles { entities.push(snaffle); }
+            for bludger in bludgers { entities.push(bludger); }
+            TurnState {
+                round: 0,
+                winner: None,
                 my_id: 0,
                 my_mp: 0,
                 enemy_mp: 0,
                 my_score: 0,
                 enemy_score: 0,
-                active_spells: Vec::new(),
-                entities: EntityStore {
-                    my_id: 0,
-                    inner: inner
-                },
+                active_spells: SpellList::new(),
+                entities: entities,
                 my_goal: Goal::from_id(0),
                 enemy_goal: Goal::from_id(1)
             }
@@ -736,25 +1430,294 @@ mod math {
             self.entities.my_id = self.my_id;
         }
 
-        pub fn draw(&self) {
-            draw::set_stroke_color(255, 255, 255, 255);
-            draw::set_stroke_width(1.);
-            draw::line(Vec2(BORDER_LEFT_X, BORDER_TOP_Y), Vec2(BORDER_RIGHT_X, BORDER_TOP_Y));
-            draw::line(Vec2(BORDER_LEFT_X, BORDER_BOTTOM_Y), Vec2(BORDER_RIGHT_X, BORDER_BOTTOM_Y));
-            draw::line(Vec2(BORDER_LEFT_X, BORDER_TOP_Y), Vec2(BORDER_LEFT_X, GOAL_Y - GOAL_RADIUS));
-            draw::line(Vec2(BORDER_RIGHT_X, BORDER_TOP_Y), Vec2(BORDER_RIGHT_X, GOAL_Y - GOAL_RADIUS));
-            draw::line(Vec2(BORDER_LEFT_X, BORDER_BOTTOM_Y), Vec2(BORDER_LEFT_X, GOAL_Y + GOAL_RADIUS));
-            draw::line(Vec2(BORDER_RIGHT_X, BORDER_BOTTOM_Y), Vec2(BORDER_RIGHT_X, GOAL_Y + GOAL_RADIUS));
-            for &pole in &POLE_POS {
-                draw::no_stroke();
-                draw::set_fill_color(100, 100, 255, 255);
-                draw::circle(pole, POLE_RADIUS);
+        pub fn draw<C: Canvas>(&self, canvas: &mut C) {
+            self.draw_field(canvas);
+            for e in self.entities.iter() {
+                if !e.dead {
+                    e.draw(canvas);
+                }
             }
+        }
+
+        // Same as draw, with entity labels and the score and MP on top.
+        pub fn draw_labelled<C: Canvas>(&self, canvas: &mut C) {
+            self.draw_field(canvas);
             for e in self.entities.iter() {
                 if !e.dead {
-                    e.draw();
+                    e.draw_labelled(canvas);
+                }
+            }
+            canvas.set_fill_color(255, 255, 255, 255);
+            canvas.text(Vec2(BORDER_LEFT_X, BORDER_TOP_Y - 50.), 200., &format!("{} pts {} MP", self.my_score, self.my_mp));
+            canvas.text(Vec2(BORDER_RIGHT_X - 2000., BORDER_TOP_Y - 50.), 200., &format!("{} pts {} MP", self.enemy_score, self.enemy_mp));
+        }
+
+        // Simulates `turns` turns with the same actions every turn and draws where everything goes,
+        // with a mark and the time (turns from now) on every collision on the way.
+        pub fn draw_prediction<C: Canvas>(&self, canvas: &mut C, turns: usize, my_actions: &[Action], enemy_actions: &[Action]) {
+            let mut state = *self;
+            let mut stats = Stats::new();
+            let mut tracer = Tracer {
+                turn: 0,
+                paths: state.entities.iter().map(|e| vec![e.pos]).collect(),
+                marks: Vec::new()
+            };
+            for turn in 0..turns {
+                if state.winner.is_some() {
+                    break;
+                }
+                let alive: Vec<bool> = state.entities.iter().map(|e| !e.dead).collect();
+                tracer.turn = turn;
+                state.simulate_with(my_actions, enemy_actions, &mut stats, &mut tracer);
+                for (i, e) in state.entities.iter().enumerate() {
+                    if alive[i] {
+                        tracer.paths[i].push(e.pos);
+                    }
+                }
+            }
+            let Tracer { paths, marks, .. } = tracer;
+
+            canvas.no_fill();
+            canvas.set_stroke_width(1.);
+            for (i, path) in paths.iter().enumerate() {
+                canvas.set_stroke_color(255, 255, 255, 128);
+                canvas.polyline(path);
+                if !state.entities[i].dead {
+                    canvas.dashed_circle(state.entities[i].pos, state.entities[i].kind.radius(), 100.);
+                }
+            }
+            for &(t, collision, contact) in &marks {
+                match collision {
+                    Collision::Entity(..)      => canvas.set_fill_color(255, 150, 0, 255),
+                    Collision::WallRebound(..) => canvas.set_fill_color(0, 255, 0, 255),
+                    Collision::PoleRebound(..) => canvas.set_fill_color(100, 100, 255, 255),
+                    Collision::None            => ()
+                }
+                canvas.no_stroke();
+                canvas.circle(contact, 60.);
+                canvas.text(contact + Vec2(80., -80.), 200., &format!("{:.2}", t));
+            }
+        }
+
+        fn draw_field<C: Canvas>(&self, canvas: &mut C) {
+            canvas.set_stroke_color(255, 255, 255, 255);
+            canvas.set_stroke_width(1.);
+            canvas.line(Vec2(BORDER_LEFT_X, BORDER_TOP_Y), Vec2(BORDER_RIGHT_X, BORDER_TOP_Y));
+            canvas.line(Vec2(BORDER_LEFT_X, BORDER_BOTTOM_Y), Vec2(BORDER_RIGHT_X, BORDER_BOTTOM_Y));
+            canvas.line(Vec2(BORDER_LEFT_X, BORDER_TOP_Y), Vec2(BORDER_LEFT_X, GOAL_Y - GOAL_RADIUS));
+            canvas.line(Vec2(BORDER_RIGHT_X, BORDER_TOP_Y), Vec2(BORDER_RIGHT_X, GOAL_Y - GOAL_RADIUS));
+            canvas.line(Vec2(BORDER_LEFT_X, BORDER_BOTTOM_Y), Vec2(BORDER_LEFT_X, GOAL_Y + GOAL_RADIUS));
+            canvas.line(Vec2(BORDER_RIGHT_X, BORDER_BOTTOM_Y), Vec2(BORDER_RIGHT_X, GOAL_Y + GOAL_RADIUS));
+            for &pole in &POLE_POS {
+                canvas.no_stroke();
+                canvas.set_fill_color(100, 100, 255, 255);
+                canvas.circle(pole, POLE_RADIUS);
+            }
+        }
+        
+        // The field as a character grid, for logs. `M`/`E` are wizards (lowercase when carrying a snaffle),
+        // `o` snaffles, `B` bludgers, `O` poles and the arrows next to things show where they're going.
+        pub fn to_ascii(&self, cols: usize, rows: usize) -> String {
+            let width = BORDER_RIGHT_X - BORDER_LEFT_X;
+            let height = BORDER_BOTTOM_Y - BORDER_TOP_Y;
+            let cell = |pos: Vec2| -> (usize, usize) {
+                let col = ((pos.0 - BORDER_LEFT_X) / width * cols as f64).floor().max(0.).min(cols as f64 - 1.) as usize;
+                let row = ((pos.1 - BORDER_TOP_Y) / height * rows as f64).floor().max(0.).min(rows as f64 - 1.) as usize;
+                (col + 1, row + 1)
+            };
+            let mut grid = vec![vec![' '; cols + 2]; rows + 2];
+            for col in 0..cols + 2 {
+                grid[0][col] = '-';
+                grid[rows + 1][col] = '-';
+            }
+            for row in 1..rows + 1 {
+                let y = BORDER_TOP_Y + (row as f64 - 0.5) * height / rows as f64;
+                let in_goal = y > GOAL_Y - GOAL_RADIUS && y < GOAL_Y + GOAL_RADIUS;
+                let side = if in_goal { ' ' } else { '|' };
+                grid[row][0] = side;
+                grid[row][cols + 1] = side;
+            }
+            for &pole in &POLE_POS {
+                let (_, row) = cell(pole);
+                let col = if pole.0 <= BORDER_LEFT_X { 0 } else { cols + 1 };
+                grid[row][col] = 'O';
+            }
+            let alive: Vec<&Entity> = self.entities.iter().filter(|e| !e.dead).collect();
+            // Arrows first so the entities themselves end up on top.
+            for e in alive.iter() {
+                if e.vel.len() < 50. {
+                    continue;
+                }
+                let (col, row) = cell(e.pos);
+                let octant = ((e.vel.angle() / (f64::consts::PI / 4.)).round() as i64 + 8) % 8;
+                let (dx, dy, arrow) = [(1, 0, '>'), (1, 1, '\\'), (0, 1, 'v'), (-1, 1, '/'), (-1, 0, '<'), (-1, -1, '\\'), (0, -1, '^'), (1, -1, '/')][octant as usize];
+                let (col, row) = ((col as i64 + dx) as usize, (row as i64 + dy) as usize);
+                if col >= 1 && col <= cols && row >= 1 && row <= rows {
+                    grid[row][col] = arrow;
+                }
+            }
+            for e in alive.iter() {
+                let (col, row) = cell(e.pos);
+                let c = match e.kind {
+                    EntityType::Wizard  => match (e.aligned_with(Faction::Me), e.linked.is_some()) {
+                        (true, false)  => 'M',
+                        (true, true)   => 'm',
+                        (false, false) => 'E',
+                        (false, true)  => 'e'
+                    },
+                    EntityType::Snaffle if e.linked.is_some() => continue, // Shown by its wizard.
+                    EntityType::Snaffle => 'o',
+                    EntityType::Bludger => 'B'
+                };
+                grid[row][col] = c;
+            }
+            let mut out = String::with_capacity((cols + 3) * (rows + 3));
+            out.push_str(&format!("Round {} | Score {} - {} | MP {} - {}", self.round, self.my_score, self.enemy_score, self.my_mp, self.enemy_mp));
+            for s in self.active_spells.iter() {
+                out.push_str(&format!(" | {:?} {} -> {} ({})", s.kind, s.caster_id, s.target_id, s.casted));
+            }
+            out.push('\n');
+            for row in grid {
+                out.extend(row);
+                out.push('\n');
+            }
+            out
+        }
+
+        // Reads everything CodinGame sends at the start of a turn. Newer versions of the game send
+        // "score magic" for both players before the entities, returns whether those were there.
+        pub fn read_input<I: Iterator<Item = String>>(&mut self, lines: &mut LineReader<I>) -> Result<bool, ParseError> {
+            let has_magic = lines.peek().map_or(false, |l| l.split_whitespace().count() == 2);
+            if has_magic {
+                let (my_score, my_mp) = parse_line!(lines, (i64, i64))?;
+                let (enemy_score, enemy_mp) = parse_line!(lines, (i64, i64))?;
+                self.my_score = my_score;
+                self.my_mp = my_mp;
+                self.enemy_score = enemy_score;
+                self.enemy_mp = enemy_mp;
+            }
+            let entity_count = parse_line!(lines, usize)?;
+            self.read_entities(lines, entity_count)?;
+            Ok(has_magic)
+        }
+
+        // Writes what CodinGame would send to player `pid` at the start of this turn.
+        pub fn write_input<W: Write>(&self, out: &mut W, pid: PlayerId) -> io::Result<()> {
+            let (my_score, my_mp, enemy_score, enemy_mp) = if pid == self.my_id { (self.my_score, self.my_mp, self.enemy_score, self.enemy_mp) }
+                                                                             else { (self.enemy_score, self.enemy_mp, self.my_score, self.my_mp) };
+            writeln!(out, "{} {}", my_score, my_mp)?;
+            writeln!(out, "{} {}", enemy_score, enemy_mp)?;
+            writeln!(out, "{}", self.entities.iter().filter(|e| !e.dead).count())?;
+            for e in self.entities.iter().filter(|e| !e.dead) {
+                let kind = match e.kind {
+                    EntityType::Wizard if (e.aligned_with(Faction::Me)) == (pid == self.my_id) => "WIZARD",
+                    EntityType::Wizard  => "OPPONENT_WIZARD",
+                    EntityType::Snaffle => "SNAFFLE",
+                    EntityType::Bludger => "BLUDGER"
+                };
+                let state = if e.kind != EntityType::Bludger && e.linked.is_some() { 1 } else { 0 };
+                writeln!(out, "{} {} {} {} {} {} {}", e.id, kind, e.pos.0 as i64, e.pos.1 as i64, e.vel.0 as i64, e.vel.1 as i64, state)?;
+            }
+            Ok(())
+        }
+
+        // Reads the entity lines of a turn, the way CodinGame sends them.
+        pub fn read_entities<I: Iterator<Item = String>>(&mut self, lines: &mut LineReader<I>, entity_count: usize) -> Result<(), ParseError> {
+            let max_entity_count = if self.entities.is_empty() { MAX_ENTITY_COUNT } else { self.entities.len() };
+            if entity_count > max_entity_count {
+                return Err(ParseError {
+                    line: lines.line_number(),
+                    token: 0,
+                    expected: "an entity count the field has room for",
+                    found: Some(entity_count.to_string())
+                });
+            }
+            let mut parsed = Vec::with_capacity(entity_count);
+            for _ in 0..entity_count {
+                let entity = parse_line!(lines, (EntityId, String, i64, i64, i64, i64, i64))?;
+                let known_type = ["WIZARD", "OPPONENT_WIZARD", "SNAFFLE", "BLUDGER"].contains(&entity.1.borrow());
+                if entity.0 >= max_entity_count || !known_type {
+                    return Err(ParseError {
+                        line: lines.line_number(),
+                        token: if known_type { 0 } else { 1 },
+                        expected: if known_type { "an entity id" } else { "an entity type" },
+                        found: Some(if known_type { entity.0.to_string() } else { entity.1 })
+                    });
+                }
+                parsed.push(entity);
+            }
+            
+            // Bludgers always have the highest ids, so even when some snaffles are gone already
+            // we know how many entities there were at the start.
+            let total_count = parsed.iter().map(|p| p.0 + 1).max().unwrap_or(0).max(entity_count);
+            if self.entities.is_empty() && total_count < TOTAL_WIZARD_COUNT + BLUDGER_COUNT {
+                return Err(ParseError {
+                    line: lines.line_number() - entity_count,
+                    token: 0,
+                    expected: "an entity count with room for all wizards and bludgers",
+                    found: Some(entity_count.to_string())
+                });
+            }
+            self.prepare(total_count);
+            
+            let mut sad_wizards_without_snaffles: Vec<(Vec2, EntityId)> = Vec::with_capacity(TOTAL_WIZARD_COUNT);
+            
+            for (eid, etype, x, y, vx, vy, state) in parsed {
+                let pos = Vec2(x as f64, y as f64);
+                let vel = Vec2(vx as f64, vy as f64);
+                match etype.borrow() {
+                    "WIZARD" => {
+                        let wiz = &mut self.entities[eid];
+                        wiz.pos = pos;
+                        wiz.vel = vel;
+                        wiz.dead = false;
+                        wiz.linked = None;
+                        if state == 1 {
+                            sad_wizards_without_snaffles.push((wiz.pos, wiz.id));
+                        }
+                    },
+                    "OPPONENT_WIZARD" => {
+                        let wiz = &mut self.entities[eid];
+                        wiz.pos = pos;
+                        wiz.vel = vel;
+                        wiz.dead = false;
+                        wiz.linked = None;
+                        if state == 1 {
+                            sad_wizards_without_snaffles.push((wiz.pos, wiz.id));
+                        }
+                    },
+                    "SNAFFLE" => {
+                        let snaffle = &mut self.entities[eid];
+                        snaffle.pos = pos;
+                        snaffle.vel = vel;
+                        snaffle.dead = false;
+                        snaffle.linked = None;
+                    },
+                    "BLUDGER" => {
+                        let bludger = &mut self.entities[eid];
+                        bludger.pos = pos;
+                        bludger.vel = vel;
+                        bludger.dead = false;
+                    },
+                    _ => unreachable!() // Checked while parsing.
+                }
+            }
+            
+            // Let's make these sad wizards happy again.
+            // Not having a snaffle is a horrible thing for a wizard.
+            // Especially when they're standing right on one.
+            // We must save the wizards!
+            //
+            // #GiveWizardsTheirSnaffles2k16
+            for sid in self.snaffle_ids() {
+                for &(pos, wid) in sad_wizards_without_snaffles.iter() {
+                    if self.entities[sid].pos == pos {
+                        self.entities[sid].linked = Some(wid);
+                        self.entities[wid].linked = Some(sid);
+                    }
                 }
             }
+            Ok(())
         }
         
         #[inline]
@@ -823,74 +1786,81 @@ mod math {
         }
         
         pub fn simulate(&mut self, my_actions: &[Action], enemy_actions: &[Action]) -> Stats {
+            let mut stats = Stats::new();
+            self.simulate_with(my_actions, enemy_actions, &mut stats, &mut NoObserver);
+            stats
+        }
+
+        // Doesn't allocate anything once `stats` has grown big enough, so hand the same one to every call.
+        pub fn simulate_with<O: Observer>(&mut self, my_actions: &[Action], enemy_actions: &[Action], stats: &mut Stats, observer: &mut O) {
+            self.simulate_impl(my_actions, enemy_actions, stats, observer, true);
+        }
+
+        // Checks every pair for collisions at every step instead of skipping the ones that can't
+        // happen yet. Slower, but it's what the scheduled version has to match exactly.
+        pub fn simulate_reference(&mut self, my_actions: &[Action], enemy_actions: &[Action]) -> Stats {
+            let mut stats = Stats::new();
+            self.simulate_impl(my_actions, enemy_actions, &mut stats, &mut NoObserver, false);
+            stats
+        }
+
+        fn simulate_impl<O: Observer>(&mut self, my_actions: &[Action], enemy_actions: &[Action], stats: &mut Stats, observer: &mut O, scheduled: bool) {
             if self.winner.is_some() {
                 panic!("Called simulate on a game that has ended!");
             }
-            // Stats
-            let mut stats = Stats {
-                collisions: Vec::with_capacity(8),
-                collected: Vec::with_capacity(4),
-                my_score_gain: 0,
-                enemy_score_gain: 0
-            };
+            stats.clear();
             // Snaffle timeouts.
             for i in 0..TOTAL_WIZARD_COUNT {
                 self.entities[i].snaffle_timeout -= 1;
             }
             // Stage 1: Thrust
-            let mut thrown_snaffles: Vec<(EntityId, Vec2, Vec2, Vec2, i64)> = Vec::with_capacity(4);
-            for i in self.my_wizard_ids() {
-                let wizard = &mut self.entities[i];
-                let my_action = my_actions[wizard.sub_id];
-                if let Action::Move(tg, thrust) = my_action {
-                    if let Some(sid) = wizard.linked {
-                        wizard.linked = None;
-                        thrown_snaffles.push((sid, wizard.pos, wizard.vel, Vec2::zero(), 0));
-                    }
-                    wizard.apply_thrust_towards(tg, thrust as f64);
-                }
-                else if let Action::Throw(tg, thrust) = my_action {
-                    if let Some(sid) = wizard.linked {
-                        wizard.linked = None;
-                        thrown_snaffles.push((sid, wizard.pos, wizard.vel, tg, thrust));
-                    }
-                }
-                else { // Casting a spell, drop snaffle
-                    if let Some(sid) = wizard.linked {
-                        wizard.linked = None;
-                        thrown_snaffles.push((sid, wizard.pos, wizard.vel, Vec2::zero(), 0));
-                    }
+            let mut actions: [Option<Action>; TOTAL_WIZARD_COUNT] = [None; TOTAL_WIZARD_COUNT];
+            for i in 0..TOTAL_WIZARD_COUNT {
+                let sub_id = self.entities[i].sub_id;
+                let action = if self.entities[i].aligned_with(Faction::Me) { my_actions[sub_id] }
+                                                                       else { enemy_actions[sub_id] };
+                actions[i] = self.check_action(i, action);
+                if actions[i] != Some(action) {
+                    let fix = match actions[i] {
+                        Some(clamped) => ActionFix::Clamped(clamped),
+                        None          => ActionFix::Rejected
+                    };
+                    stats.invalid_actions.push((i, action, fix));
                 }
             }
-            for i in self.enemy_wizard_ids() {
+            let mut thrown_snaffles: [Option<(EntityId, Vec2, Vec2, Vec2, i64)>; TOTAL_WIZARD_COUNT] = [None; TOTAL_WIZARD_COUNT];
+            for i in 0..TOTAL_WIZARD_COUNT {
                 let wizard = &mut self.entities[i];
-                let enemy_action = enemy_actions[wizard.sub_id];
-                if let Action::Move(tg, thrust) = enemy_action {
+                if let Some(Action::Move(tg, thrust)) = actions[i] {
                     if let Some(sid) = wizard.linked {
                         wizard.linked = None;
-                        thrown_snaffles.push((sid, wizard.pos, wizard.vel, Vec2::zero(), 0));
+                        thrown_snaffles[i] = Some((sid, wizard.pos, wizard.vel, Vec2::zero(), 0));
                     }
                     wizard.apply_thrust_towards(tg, thrust as f64);
+                    observer.thrust(i, tg, thrust as f64);
                 }
-                else if let Action::Throw(tg, thrust) = enemy_action {
+                else if let Some(Action::Throw(tg, thrust)) = actions[i] {
                     if let Some(sid) = wizard.linked {
                         wizard.linked = None;
-                        thrown_snaffles.push((sid, wizard.pos, wizard.vel, tg, thrust));
+                        thrown_snaffles[i] = Some((sid, wizard.pos, wizard.vel, tg, thrust));
                     }
                 }
-                else { // Casting a spell, drop snaffle
+                else { // Casting a spell (or doing something invalid), drop snaffle
                     if let Some(sid) = wizard.linked {
                         wizard.linked = None;
-                        thrown_snaffles.push((sid, wizard.pos, wizard.vel, Vec2::zero(), 0));
+                        thrown_snaffles[i] = Some((sid, wizard.pos, wizard.vel, Vec2::zero(), 0));
                     }
                 }
             }
-            for (sid, pos, vel, tg, thrust) in thrown_snaffles {
+            for &(sid, pos, vel, tg, thrust) in thrown_snaffles.iter().flatten() {
                 let snaffle = &mut self.entities[sid];
                 snaffle.vel = vel;
                 snaffle.pos = pos;
                 snaffle.linked = None;
                 snaffle.apply_thrust_towards(tg, thrust as f64);
+                if thrust > 0 {
+                    observer.thrust(sid, tg, thrust as f64);
+                }
             }
             for i in self.bludger_ids() {
                 let mut closest = None;
@@ -898,6 +1868,9 @@ mod math {
                     if self.entities[i].linked == Some(wid) {
                         continue;
                     }
+                    if self.is_obliviated(i, self.entities[wid].faction) {
+                        continue;
+                    }
                     let dist = self.entities[wid].pos.distance_to(self.entities[i].pos);
                     closest = Some(match closest {
                         Some((_, best_dist)) if dist < best_dist => (wid, dist),
@@ -905,13 +1878,15 @@ mod math {
                         None => (wid, dist)
                     });
                 }
-                let best_wid = closest.unwrap().0;
-                let tg = self.entities[best_wid].pos;
-                self.entities[i].apply_thrust_towards(tg, 1000.);
+                // Both teams may have obliviated this bludger, then it just drifts.
+                if let Some((best_wid, _)) = closest {
+                    let tg = self.entities[best_wid].pos;
+                    self.entities[i].apply_thrust_towards(tg, 1000.);
+                    observer.thrust(i, tg, 1000.);
+                }
             }
-            // TODO: thrust caps (though really my AI never uses too high thrusts)
             // Stage 2: Spells
-            for s in &mut self.active_spells {
+            for s in self.active_spells.iter_mut() {
                 match s.kind {
                     SpellType::Flipendo => {
                         let towards = self.entities[s.target_id].pos - self.entities[s.caster_id].pos;
@@ -943,45 +1918,85 @@ mod math {
                     SpellType::Petrificus => {
                         self.entities[s.target_id].vel = Vec2::zero();
                     },
-                    _ => ()
+                    SpellType::Obliviate => () // Already taken care of when the bludgers picked their targets.
                 }
+                observer.spell_effect(s);
             }
             let round = self.round;
             self.active_spells.retain(|s| round - s.casted < s.duration);
-            // TODO: obliviate
             // Stage 3: Movement
+            let mut goal_line_crossings = [1.; MAX_ENTITY_COUNT];
+            // Earliest time each entity could possibly hit a wall or a pole and each pair could possibly
+            // collide, only updated when one of them bounces.
+            let mut border_bounds = [0.; MAX_ENTITY_COUNT];
+            let mut pair_bounds = [[0.; MAX_ENTITY_COUNT]; MAX_ENTITY_COUNT];
+            if scheduled {
+                for i in 0..self.entities.len() {
+                    border_bounds[i] = self.border_collision_bound(i);
+                    for j in (i + 1)..self.entities.len() {
+                        pair_bounds[i][j] = self.pair_collision_bound(i, j);
+                    }
+                }
+            }
             let mut t = 0.;
+            // Like the referee, the collision that was just handled doesn't count again right away. Overlapping
+            // circles collide at 0, and whatever the response did to them, they'd otherwise keep on doing it.
+            let mut last_collision = (Collision::None, 0.);
+            let mut steps = 0;
+            let mut abandoned = false;
             while t < 1. {
                 let mut earliest_collision_time = 1. - t;
                 let mut collision = Collision::None;
+                steps += 1;
+                if steps > MAX_COLLISION_STEPS && !abandoned {
+                    abandoned = true;
+                    stats.collisions_abandoned = true;
+                }
+                let repeated = |candidate: Collision, col_t: f64| col_t == 0. && last_collision == (candidate, t);
                 for i in 0..self.entities.len() {
-                    if self.entities[i].dead {
+                    if abandoned || self.entities[i].dead {
                         continue;
                     }
-                    if let Some((col_t, is_vertical)) = self.rebound_time(i) {
-                        if col_t < earliest_collision_time {
-                            earliest_collision_time = col_t;
-                            collision = Collision::WallRebound(i, is_vertical);
+                    if !scheduled || border_bounds[i] - t <= earliest_collision_time {
+                        if let Some((col_t, is_vertical)) = self.rebound_time(i) {
+                            if col_t < earliest_collision_time && !repeated(Collision::WallRebound(i, is_vertical), col_t) {
+                                earliest_collision_time = col_t;
+                                collision = Collision::WallRebound(i, is_vertical);
+                            }
                         }
-                    }
-                    if let Some((col_t, pole_id)) = self.pole_rebound_time(i) {
-                        if col_t < earliest_collision_time {
-                            earliest_collision_time = col_t;
-                            collision = Collision::PoleRebound(i, pole_id);
+                        if let Some((col_t, pole_id)) = self.pole_rebound_time(i) {
+                            if col_t < earliest_collision_time && !repeated(Collision::PoleRebound(i, pole_id), col_t) {
+                                earliest_collision_time = col_t;
+                                collision = Collision::PoleRebound(i, pole_id);
+                            }
                         }
                     }
                     for j in (i + 1)..self.entities.len() {
                         if self.entities[j].dead {
                             continue;
                         }
+                        if scheduled && pair_bounds[i][j] - t > earliest_collision_time {
+                            continue;
+                        }
                         if let Some(col_t) = self.collision_time(i, j) {
-                            if col_t < earliest_collision_time {
+                            if col_t < earliest_collision_time && !repeated(Collision::Entity(i, j), col_t) {
                                 earliest_collision_time = col_t;
                                 collision = Collision::Entity(i, j);
                             }
                         }
                     }
                 }
+                for sid in self.snaffle_ids() {
+                    let snaffle = &self.entities[sid];
+                    if snaffle.dead || snaffle.vel.0 == 0. {
+                        continue;
+                    }
+                    let line = if snaffle.vel.0 > 0. { BORDER_RIGHT_X } else { BORDER_LEFT_X };
+                    let crossing = (line - snaffle.pos.0) / snaffle.vel.0;
+                    if crossing >= 0. && crossing < earliest_collision_time {
+                        goal_line_crossings[sid] = t + crossing;
+                    }
+                }
                 for k in 0..self.entities.len() {
                     self.fast_forward(k, earliest_collision_time);
                 }
@@ -989,8 +2004,13 @@ mod math {
                 if VERBOSE {
                     debug!("[{}] {:?}", t, collision);
                 }
+                if collision != Collision::None {
+                    observer.collision(self, t, collision);
+                    last_collision = (collision, t);
+                }
                 match collision {
                     Collision::WallRebound(i, is_vertical) => {
+                        stats.wall_bounces += 1;
                         let vel = self.entities[i].vel;
                         if is_vertical {
                             self.entities[i].vel = Vec2(vel.0, -vel.1);
@@ -1000,14 +2020,45 @@ mod math {
                         }
                     },
                     Collision::PoleRebound(i, pid) => {
+                        stats.pole_bounces += 1;
                         self.pole_rebound_response(i, pid);
                     },
                     Collision::Entity(i, j) => {
                         stats.collisions.push((i, j));
-                        self.collision_response(&mut stats, i, j);
+                        if self.entities[i].is_a(EntityType::Wizard) && self.entities[j].is_a(EntityType::Bludger) {
+                            stats.bludger_hits[i] += 1;
+                        }
+                        self.collision_response(stats, observer, i, j);
                     },
                     Collision::None => ()
                 }
+                // Rebounds off something the entity is already past come out negative, and the bounds
+                // only hold going forward.
+                if scheduled && earliest_collision_time < 0. {
+                    for i in 0..self.entities.len() {
+                        border_bounds[i] = t + self.border_collision_bound(i);
+                        for j in (i + 1)..self.entities.len() {
+                            pair_bounds[i][j] = t + self.pair_collision_bound(i, j);
+                        }
+                    }
+                }
+                else if scheduled {
+                    let (a, b) = match collision {
+                        Collision::WallRebound(i, _) | Collision::PoleRebound(i, _) => (i, i),
+                        Collision::Entity(i, j)                                      => (i, j),
+                        Collision::None                                              => continue
+                    };
+                    border_bounds[a] = t + self.border_collision_bound(a);
+                    border_bounds[b] = t + self.border_collision_bound(b);
+                    for k in 0..self.entities.len() {
+                        for &changed in &[a, b] {
+                            if k != changed {
+                                let (i, j) = if k < changed { (k, changed) } else { (changed, k) };
+                                pair_bounds[i][j] = t + self.pair_collision_bound(i, j);
+                            }
+                        }
+                    }
+                }
             }
             // Stage 4: Friction
             for e in self.entities.iter_mut() {
@@ -1015,15 +2066,20 @@ mod math {
             }
             // Stage 5: Rounding
             for e in self.entities.iter_mut() {
-                e.vel = e.vel.round();
-                e.pos = e.pos.round();
+                e.vel = VELOCITY_ROUNDING.apply_vec(e.vel);
+                e.pos = POSITION_ROUNDING.apply_vec(e.pos);
             }
             // Goals
             for sid in self.snaffle_ids() {
                 let snaffle = &mut self.entities[sid];
                 if snaffle.dead { continue; }
+                // Rounding can push a snaffle just short of the line over it, that counts as the end of the turn.
+                let goal_time = goal_line_crossings[sid];
                 if snaffle.pos.0 >= 16000. {
                     snaffle.dead = true;
+                    let scorer = if self.my_id == 0 { Faction::Me } else { Faction::Enemy };
+                    stats.goals.push((sid, scorer, goal_time));
+                    observer.goal(sid, scorer);
                     if self.my_id == 0 {
                         self.my_score += 1;
                         stats.my_score_gain += 1;
@@ -1035,6 +2091,9 @@ mod math {
                 }
                 else if snaffle.pos.0 <= 0. {
                     snaffle.dead = true;
+                    let scorer = if self.my_id == 0 { Faction::Enemy } else { Faction::Me };
+                    stats.goals.push((sid, scorer, goal_time));
+                    observer.goal(sid, scorer);
                     if self.my_id == 0 {
                         self.enemy_score += 1;
                         stats.enemy_score_gain += 1;
@@ -1051,33 +2110,18 @@ mod math {
                 self.entities[sid].vel = self.entities[wid].vel;
             }
             // Spell casting
-            for i in self.my_wizard_ids() {
-                let wizard = &mut self.entities[i];
-                if let Action::Spell(kind, eid) = my_actions[wizard.sub_id] {
-                    if self.my_mp >= kind.cost() {
-                        let spell = Spell::new(kind, wizard.id, eid, self.round);
-                        if kind == SpellType::Petrificus {
-                            self.active_spells.insert(0, spell);
-                        }
-                        else {
-                            self.active_spells.push(spell);
-                        }
-                        self.my_mp -= kind.cost();
+            for i in 0..TOTAL_WIZARD_COUNT {
+                if let Some(Action::Spell(kind, eid)) = actions[i] {
+                    let mine = self.entities[i].aligned_with(Faction::Me);
+                    let mp = if mine { &mut self.my_mp } else { &mut self.enemy_mp };
+                    if *mp >= kind.cost() {
+                        *mp -= kind.cost();
+                        self.add_spell(kind, i, eid);
+                        stats.spells_cast.push((i, kind, eid));
+                        observer.cast(i, kind, eid);
                     }
-                }
-            }
-            for i in self.enemy_wizard_ids() {
-                let wizard = &mut self.entities[i];
-                if let Action::Spell(kind, eid) = enemy_actions[wizard.sub_id] {
-                    if self.enemy_mp >= kind.cost() {
-                        let spell = Spell::new(kind, wizard.id, eid, self.round);
-                        if kind == SpellType::Petrificus {
-                            self.active_spells.insert(0, spell);
-                        }
-                        else {
-                            self.active_spells.push(spell);
-                        }
-                        self.enemy_mp -= kind.cost();
+                    else {
+                        stats.spells_fizzled.push((i, kind, eid));
                     }
                 }
             }
@@ -1091,21 +2135,70 @@ mod math {
                 else {
                     self.winner = Some(Faction::Neutral);
                 }
-                return stats;
+                return;
             }
             let snaffle_count = self.entities.len() - TOTAL_WIZARD_COUNT - BLUDGER_COUNT;
             if self.my_score > snaffle_count as i64 / 2 {
                 self.winner = Some(Faction::Me);
-                return stats;
+                return;
             }
             if self.enemy_score > snaffle_count as i64 / 2 {
                 self.winner = Some(Faction::Enemy);
-                return stats;
+                return;
             }
-            self.my_mp += 1;
-            self.enemy_mp += 1;
+            self.my_mp = (self.my_mp + 1).min(MAX_MP);
+            self.enemy_mp = (self.enemy_mp + 1).min(MAX_MP);
             self.round += 1;
-            stats
+        }
+
+        // Petrificus goes first so it can't undo the other spells of the turn.
+        pub fn add_spell(&mut self, kind: SpellType, caster_id: EntityId, target_id: EntityId) {
+            let round = self.round;
+            self.add_spell_cast_in(kind, caster_id, target_id, round);
+        }
+
+        // For spells we only find out about after the fact.
+        pub fn add_spell_cast_in(&mut self, kind: SpellType, caster_id: EntityId, target_id: EntityId, round: usize) {
+            let spell = Spell::new(kind, caster_id, target_id, round);
+            if kind == SpellType::Petrificus {
+                self.active_spells.insert(0, spell);
+            }
+            else {
+                self.active_spells.push(spell);
+            }
+        }
+
+        // Drops the spells that won't have any effect anymore when simulating the current round.
+        pub fn expire_spells(&mut self) {
+            let round = self.round;
+            self.active_spells.retain(|s| round - s.casted <= s.duration);
+        }
+
+        // Applies the referee limits to an action. Thrusts out of range get clamped,
+        // spells on things they can't target get rejected altogether.
+        pub fn check_action(&self, wid: EntityId, action: Action) -> Option<Action> {
+            match action {
+                // Infinite ones too, "1e400" parses just fine and sends the wizard off with a NaN velocity.
+                Action::Move(tg, _) | Action::Throw(tg, _) if !tg.0.is_finite() || !tg.1.is_finite() => None,
+                Action::Move(tg, thrust) => Some(Action::Move(tg, thrust.max(0).min(MAX_MOVE_THRUST))),
+                Action::Throw(tg, power) => Some(Action::Throw(tg, power.max(0).min(MAX_THROW_POWER))),
+                Action::Spell(kind, eid) => {
+                    if eid >= self.entities.len() || eid == wid || self.entities[eid].dead {
+                        return None;
+                    }
+                    let target = &self.entities[eid];
+                    let friendly = target.aligned_with(self.entities[wid].faction);
+                    if kind.can_target(target.kind, friendly) { Some(action) }
+                                                          else { None }
+                }
+            }
+        }
+
+        // Whether the bludger `bid` is currently ignoring the wizards of `faction`.
+        pub fn is_obliviated(&self, bid: EntityId, faction: Faction) -> bool {
+            self.active_spells.iter().any(|s| s.kind == SpellType::Obliviate
+                                           && s.target_id == bid
+                                           && self.entities[s.caster_id].aligned_with(faction))
         }
 
         pub fn pole_rebound_time(&self, id: EntityId) -> Option<(f64, PoleId)> {
@@ -1202,6 +2295,38 @@ mod math {
             circle_collision_time(dpos, dvel, radius_sum)
         }
         
+        // Same as pair_collision_bound, for rebound_time and pole_rebound_time.
+        pub fn border_collision_bound(&self, id: EntityId) -> f64 {
+            let ent = &self.entities[id];
+            let rad = ent.kind.radius();
+            let mut gap = (ent.pos.0 - BORDER_LEFT_X).min(BORDER_RIGHT_X - ent.pos.0)
+                              .min(ent.pos.1 - BORDER_TOP_Y).min(BORDER_BOTTOM_Y - ent.pos.1) - rad;
+            for &pole in &POLE_POS {
+                gap = gap.min(ent.pos.distance_to(pole) - POLE_RADIUS - rad);
+            }
+            gap -= COLLISION_BOUND_MARGIN;
+            if gap <= 0. {
+                return 0.;
+            }
+            let speed = ent.vel.len();
+            if speed == 0. { f64::INFINITY }
+                      else { gap / speed }
+        }
+
+        // A lower bound on the time until collision_time could give something for these two. It's loose
+        // on purpose so rounding errors can never make it skip a collision the exact check would find.
+        pub fn pair_collision_bound(&self, eaid: EntityId, ebid: EntityId) -> f64 {
+            let ea = &self.entities[eaid];
+            let eb = &self.entities[ebid];
+            let gap = ea.pos.distance_to(eb.pos) - ea.kind.radius() - eb.kind.radius() - COLLISION_BOUND_MARGIN;
+            if gap <= 0. {
+                return 0.;
+            }
+            let speed = (eb.vel - ea.vel).len();
+            if speed == 0. { f64::INFINITY }
+                      else { gap / speed }
+        }
+
         pub fn fast_forward(&mut self, id: EntityId, t: f64) {
             if t > 0. {
                 let e = &mut self.entities[id];
@@ -1209,7 +2334,7 @@ mod math {
             }
         }
         
-        pub fn collision_response(&mut self, stats: &mut Stats, eaid: EntityId, ebid: EntityId) {
+        pub fn collision_response<O: Observer>(&mut self, stats: &mut Stats, observer: &mut O, eaid: EntityId, ebid: EntityId) {
             if eaid == ebid { panic!("Cannot do a collision response with an entity and itself!"); }
             if eaid >= self.entities.len() { panic!("Index out of bounds!"); }
             if ebid >= self.entities.len() { panic!("Index out of bounds!"); }
@@ -1225,6 +2350,7 @@ mod math {
                     eb.linked = Some(eaid);
                     ea.snaffle_timeout = 3;
                     stats.collected.push((eaid, ebid));
+                    observer.pickup(eaid, ebid);
                     return;
                 }
             }
@@ -1245,9 +2371,11 @@ mod math {
         }
     }
     
-    #[derive(Clone, Debug)]
+    // Everything lives inline so copying a TurnState is a plain memcpy.
+    #[derive(Copy, Clone)]
     pub struct EntityStore {
-        inner: Vec<Entity>,
+        inner: [Entity; MAX_ENTITY_COUNT],
+        len: usize,
         my_id: PlayerId
     }
     
@@ -1256,44 +2384,60 @@ mod math {
         
         #[inline]
         fn deref(&self) -> &[Entity] {
-            &self.inner
+            &self.inner[..self.len]
         }
     }
     
     impl DerefMut for EntityStore {
         #[inline]
         fn deref_mut(&mut self) -> &mut [Entity] {
-            &mut self.inner
+            &mut self.inner[..self.len]
+        }
+    }
+    
+    impl fmt::Debug for EntityStore {
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+            fmt.debug_list().entries(self.iter()).finish()
         }
     }
     
     impl EntityStore {
         pub fn new(my_id: PlayerId) -> EntityStore {
             EntityStore {
-                inner: Vec::with_capacity(MAX_ENTITY_COUNT),
+                inner: [Entity::new_snaffle(0, 0, Vec2::zero(), Vec2::zero()); MAX_ENTITY_COUNT],
+                len: 0,
                 my_id: my_id
             }
         }
         
+        #[inline]
+        pub fn push(&mut self, entity: Entity) {
+            if self.len == MAX_ENTITY_COUNT {
+                panic!("Cannot have more than {} entities!", MAX_ENTITY_COUNT);
+            }
+            self.inner[self.len] = entity;
+            self.len += 1;
+        }
+        
         pub fn prepare(&mut self, entity_count: usize) {
-            if self.inner.is_empty() {
+            if self.len == 0 {
                 let mut i = 0;
                 for j in 0..WIZARD_COUNT * 2 {
                     let faction = if self.my_id == 0 { if i < WIZARD_COUNT { Faction::Me    } else { Faction::Enemy } }
                                                 else { if i < WIZARD_COUNT { Faction::Enemy } else { Faction::Me    } };
-                    self.inner.push(Entity::new_wizard(i, j % WIZARD_COUNT, WIZARD_POS[i], Vec2::zero(), faction, None));
+                    self.push(Entity::new_wizard(i, j % WIZARD_COUNT, WIZARD_POS[i], Vec2::zero(), faction, None));
                     i += 1;
                 }
                 for j in 0..entity_count - WIZARD_COUNT * 2 - BLUDGER_COUNT {
-                    self.inner.push(Entity::new_snaffle(i, j, Vec2::zero(), Vec2::zero()));
+                    self.push(Entity::new_snaffle(i, j, Vec2::zero(), Vec2::zero()));
                     i += 1;
                 }
                 for j in 0..BLUDGER_COUNT {
-                    self.inner.push(Entity::new_bludger(i, j, BLUDGER_POS[j], Vec2::zero()));
+                    self.push(Entity::new_bludger(i, j, BLUDGER_POS[j], Vec2::zero()));
                     i += 1;
                 }
             }
-            for e in self.inner.iter_mut() {
+            for e in self.iter_mut() {
                 e.dead = true;
             }
         }
@@ -1364,16 +2508,16 @@ mod math {
         
         #[inline]
         pub fn snaffle_ids(&self) -> Range<EntityId> {
-            TOTAL_WIZARD_COUNT..self.inner.len() - 2
+            TOTAL_WIZARD_COUNT..self.len - 2
         }
         
         #[inline]
         pub fn bludger_ids(&self) -> Range<EntityId> {
-            self.inner.len() - 2..self.inner.len()
+            self.len - 2..self.len
         }
     }
     
-    #[derive(Clone, Debug)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
     pub struct Entity {
         pub id: EntityId,
         pub sub_id: SubId,
@@ -1439,18 +2583,30 @@ mod math {
             self.faction == faction
         }
         
-        pub fn draw(&self) {
-            draw::no_stroke();
+        pub fn draw<C: Canvas>(&self, canvas: &mut C) {
+            canvas.no_stroke();
             match self.kind {
                 EntityType::Wizard  => if self.aligned_with(Faction::Me){
-                    draw::set_fill_color(255, 255, 0, 255);
+                    canvas.set_fill_color(255, 255, 0, 255);
                 } else {
-                    draw::set_fill_color(255, 0, 0, 255);
+                    canvas.set_fill_color(255, 0, 0, 255);
                 },
-                EntityType::Snaffle => draw::set_fill_color(255, 99, 255, 255),
-                EntityType::Bludger => draw::set_fill_color(0, 255, 255, 255)
+                EntityType::Snaffle => canvas.set_fill_color(255, 99, 255, 255),
+                EntityType::Bludger => canvas.set_fill_color(0, 255, 255, 255)
+            }
+            canvas.circle(self.pos, self.kind.radius());
+        }
+
+        // Shows the id and sub id next to the entity, plus the snaffle timeout of wizards that have one.
+        pub fn draw_labelled<C: Canvas>(&self, canvas: &mut C) {
+            self.draw(canvas);
+            let mut label = format!("{}/{}", self.id, self.sub_id);
+            if self.kind == EntityType::Wizard && self.snaffle_timeout > 0 {
+                label.push_str(&format!(" T{}", self.snaffle_timeout));
             }
-            draw::circle(self.pos, self.kind.radius());
+            let radius = self.kind.radius();
+            canvas.set_fill_color(255, 255, 255, 255);
+            canvas.text(self.pos + Vec2(radius, -radius), 300., &label);
         }
         
         pub fn apply_thrust(&mut self, thrust: Vec2) {
@@ -1484,49 +2640,61 @@ mod math {
     }
 
     impl FromStr for Action {
-        type Err = ();
-        fn from_str(s: &str) -> Result<Action, ()> {
-            let mut sp = s.split(" ");
-            let cmd = sp.next().unwrap();
-            Ok(match cmd {
+        type Err = ParseError;
+        fn from_str(s: &str) -> Result<Action, ParseError> {
+            let mut sp = s.split_whitespace();
+            let i = &mut 0;
+            let cmd: String = token(&mut sp, 0, i, "an action")?;
+            Ok(match cmd.borrow() {
                 "MOVE" => {
-                    let x: f64 = sp.next().unwrap().parse().unwrap();
-                    let y: f64 = sp.next().unwrap().parse().unwrap();
-                    let thrust: i64 = sp.next().unwrap().parse().unwrap();
+                    let x: f64 = token(&mut sp, 0, i, "f64")?;
+                    let y: f64 = token(&mut sp, 0, i, "f64")?;
+                    let thrust: i64 = token(&mut sp, 0, i, "i64")?;
                     Action::Move(Vec2(x, y), thrust)
                 },
                 "THROW" => {
-                    let x: f64 = sp.next().unwrap().parse().unwrap();
-                    let y: f64 = sp.next().unwrap().parse().unwrap();
-                    let thrust: i64 = sp.next().unwrap().parse().unwrap();
+                    let x: f64 = token(&mut sp, 0, i, "f64")?;
+                    let y: f64 = token(&mut sp, 0, i, "f64")?;
+                    let thrust: i64 = token(&mut sp, 0, i, "i64")?;
                     Action::Throw(Vec2(x, y), thrust)
                 },
                 "OBLIVIATE" => {
-                    let eid: EntityId = sp.next().unwrap().parse().unwrap();
+                    let eid: EntityId = token(&mut sp, 0, i, "EntityId")?;
                     Action::Spell(SpellType::Obliviate, eid)
                 },
                 "PETRIFICUS" => {
-                    let eid: EntityId = sp.next().unwrap().parse().unwrap();
+                    let eid: EntityId = token(&mut sp, 0, i, "EntityId")?;
                     Action::Spell(SpellType::Petrificus, eid)
                 },
                 "ACCIO" => {
-                    let eid: EntityId = sp.next().unwrap().parse().unwrap();
+                    let eid: EntityId = token(&mut sp, 0, i, "EntityId")?;
                     Action::Spell(SpellType::Accio, eid)
                 },
                 "FLIPENDO" => {
-                    let eid: EntityId = sp.next().unwrap().parse().unwrap();
+                    let eid: EntityId = token(&mut sp, 0, i, "EntityId")?;
                     Action::Spell(SpellType::Flipendo, eid)
                 },
-                _ => return Err(())
+                _ => return Err(ParseError {
+                    line: 0,
+                    token: 0,
+                    expected: "an action",
+                    found: Some(cmd)
+                })
             })
         }
     }
     
-    #[derive(Clone)]
+    #[derive(Copy, Clone)]
     pub struct Goal {
         pub center: Vec2
     }
     
+    impl fmt::Display for TurnState {
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+            write!(fmt, "{}", self.to_ascii(80, 25))
+        }
+    }
+    
     impl fmt::Debug for Goal {
         fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
             write!(fmt, "Goal({:?})", self.center)
@@ -1551,5 +2719,1144 @@ mod math {
             (dist + (self.center.0 - tg.0).powi(2)).sqrt()
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use parse::LineReader;
+        #[cfg(feature = "prng")] use prng::Prng;
+
+        #[cfg(feature = "prng")]
+        fn random_actions(rng: &mut Prng, state: &TurnState) -> Vec<Action> {
+            (0..WIZARD_COUNT).map(|_| match rng.below(6) {
+                0 => {
+                    let kind = [SpellType::Flipendo, SpellType::Accio, SpellType::Obliviate, SpellType::Petrificus][rng.below(4) as usize];
+                    Action::Spell(kind, rng.range_usize(0, state.entities.len()))
+                },
+                // Some of them aimed outside the field, to get snaffles into the goals and against the poles.
+                1 | 2 => Action::Throw(rng.vec2_in_rect(Vec2(-2000., -2000.), Vec2(18000., 9500.)), rng.range(0, MAX_THROW_POWER + 1)),
+                _ => Action::Move(rng.vec2_in_rect(Vec2(BORDER_LEFT_X, BORDER_TOP_Y), Vec2(BORDER_RIGHT_X, BORDER_BOTTOM_Y)), rng.range(0, MAX_MOVE_THRUST + 1))
+            }).collect()
+        }
+
+        // Where each bludger was sent during the last turn.
+        struct BludgerTargets(Vec<(EntityId, Vec2)>);
+
+        impl Observer for BludgerTargets {
+            fn thrust(&mut self, id: EntityId, towards: Vec2, _power: f64) {
+                self.0.push((id, towards));
+            }
+        }
+
+        #[test]
+        fn obliviated_bludger_ignores_the_casters_team() {
+            let mut state = TurnState::init(TOTAL_WIZARD_COUNT + 1 + BLUDGER_COUNT);
+            let sid = state.snaffle_ids().start;
+            let bid = state.bludger_ids().start;
+            state.entities[sid].pos = Vec2(8000., 7000.);
+            // Our wizard is right next to the bludger, the closest enemy is way off to the right.
+            state.entities[0].pos = Vec2(6000., 3750.);
+            state.entities[2].pos = Vec2(14000., 3750.);
+            state.my_mp = MAX_MP;
+            let coast = [Action::Move(Vec2(8000., 3750.), 0); WIZARD_COUNT];
+            let obliviate = [Action::Spell(SpellType::Obliviate, bid), coast[1]];
+            let mut stats = Stats::new();
+            for turn in 0..OBLIVIATE_DURATION + 2 {
+                let ours = state.entities[0].pos;
+                let theirs = state.entities[2].pos;
+                let mut targets = BludgerTargets(Vec::new());
+                state.simulate_with(if turn == 0 { &obliviate } else { &coast }, &coast, &mut stats, &mut targets);
+                let target = targets.0.iter().find(|&&(id, _)| id == bid).map(|&(_, tg)| tg);
+                // The spell only takes effect the turn after it was cast.
+                let obliviated = turn >= 1 && turn <= OBLIVIATE_DURATION;
+                assert!(target == Some(if obliviated { theirs } else { ours }), "turn {}: went for {:?}", turn, target);
+            }
+        }
+
+        #[test]
+        fn invalid_actions_are_clamped_or_rejected() {
+            let mut state = TurnState::init(TOTAL_WIZARD_COUNT + 2 + BLUDGER_COUNT);
+            let sids = state.snaffle_ids();
+            state.entities[sids.start].pos = Vec2(8000., 1000.);
+            state.entities[sids.start + 1].dead = true;
+            state.my_mp = MAX_MP;
+            state.enemy_mp = MAX_MP;
+            let tg = Vec2(8000., 3750.);
+            let my_actions = [Action::Move(tg, 9000), Action::Throw(tg, -5)];
+            // Petrificus on a teammate and Flipendo on a snaffle that has already scored.
+            let enemy_actions = [Action::Spell(SpellType::Petrificus, 3), Action::Spell(SpellType::Flipendo, sids.start + 1)];
+            let stats = state.simulate(&my_actions, &enemy_actions);
+            assert_eq!(stats.invalid_actions, vec![
+                (0, my_actions[0], ActionFix::Clamped(Action::Move(tg, MAX_MOVE_THRUST))),
+                (1, my_actions[1], ActionFix::Clamped(Action::Throw(tg, 0))),
+                (2, enemy_actions[0], ActionFix::Rejected),
+                (3, enemy_actions[1], ActionFix::Rejected)
+            ]);
+            assert_eq!(state.enemy_mp, MAX_MP);
+        }
+
+        // Reads back what write_input gave player `pid`, the way cg does.
+        fn read_back(state: &TurnState, pid: PlayerId) -> TurnState {
+            let mut input = Vec::new();
+            state.write_input(&mut input, pid).unwrap();
+            let text = String::from_utf8(input).unwrap();
+            let mut read = TurnState::new(pid);
+            read.read_input(&mut LineReader::new(text.lines().map(String::from))).unwrap();
+            read
+        }
+
+        #[test]
+        #[cfg(feature = "prng")]
+        fn written_input_reads_back_the_same() {
+            let (mut held, mut scored) = (false, false);
+            for seed in 0..20 {
+                let mut rng = Prng::new(seed);
+                let mut state = TurnState::generate(seed);
+                while state.winner.is_none() {
+                    for pid in 0..2 {
+                        let read = read_back(&state, pid);
+                        let expected = if pid == 0 { (state.my_score, state.my_mp, state.enemy_score, state.enemy_mp) }
+                                               else { (state.enemy_score, state.enemy_mp, state.my_score, state.my_mp) };
+                        assert_eq!((read.my_score, read.my_mp, read.enemy_score, read.enemy_mp), expected);
+                        assert_eq!(read.entities.len(), state.entities.len());
+                        for (r, e) in read.entities.iter().zip(state.entities.iter()) {
+                            assert_eq!(r.dead, e.dead, "game {}, round {}, entity {}", seed, state.round, e.id);
+                            // Scored snaffles aren't in the input at all.
+                            if e.dead {
+                                continue;
+                            }
+                            assert_eq!((r.pos, r.vel), (e.pos, e.vel), "game {}, round {}, entity {}", seed, state.round, e.id);
+                            // Bludgers remember the last wizard they hit, the input doesn't.
+                            if e.kind != EntityType::Bludger {
+                                assert_eq!(r.linked, e.linked, "game {}, round {}, entity {}", seed, state.round, e.id);
+                            }
+                        }
+                    }
+                    held |= state.snaffles().iter().any(|s| s.linked.is_some());
+                    scored |= state.my_score + state.enemy_score > 0;
+                    let my_actions = random_actions(&mut rng, &state);
+                    let enemy_actions = random_actions(&mut rng, &state);
+                    state.simulate(&my_actions, &enemy_actions);
+                }
+            }
+            assert!(held && scored);
+        }
+
+        // Every collision handled during the last turn, with when it happened.
+        struct Collisions(Vec<(f64, Collision)>);
+
+        impl Observer for Collisions {
+            fn collision(&mut self, _state: &TurnState, t: f64, collision: Collision) {
+                self.0.push((t, collision));
+            }
+        }
+
+        // Everything piled up on the same spot and pushed into the top wall, they keep bouncing
+        // off each other and the wall without ever getting anywhere.
+        #[test]
+        fn stacked_entities_give_up_on_collisions() {
+            let mut state = TurnState::init(TOTAL_WIZARD_COUNT + 3 + BLUDGER_COUNT);
+            let count = state.entities.len();
+            for (i, e) in state.entities.iter_mut().enumerate() {
+                let angle = i as f64 * 2. * ::std::f64::consts::PI / count as f64;
+                e.pos = Vec2(8000., 100.) + Vec2(angle.cos(), angle.sin()) * 10.;
+            }
+            let coast = [Action::Move(Vec2(8000., 3750.), 0); WIZARD_COUNT];
+            let mut reference = state;
+            let stats = state.simulate(&coast, &coast);
+            let reference_stats = reference.simulate_reference(&coast, &coast);
+            assert!(stats.collisions_abandoned);
+            assert!(reference_stats.collisions_abandoned);
+        }
+
+        // Far too deep into each other for one bounce to get them apart.
+        #[test]
+        fn repeated_zero_time_collision_is_skipped() {
+            let mut state = TurnState::init(TOTAL_WIZARD_COUNT + 1 + BLUDGER_COUNT);
+            let sid = state.snaffle_ids().start;
+            state.entities[sid].pos = Vec2(8000., 7000.);
+            state.entities[0].pos = Vec2(8000., 1000.);
+            state.entities[0].vel = Vec2(1000., 0.);
+            state.entities[2].pos = Vec2(8010., 1000.);
+            state.entities[2].vel = Vec2(-1000., 0.);
+            let coast = [Action::Move(Vec2(8000., 3750.), 0); WIZARD_COUNT];
+            let mut stats = Stats::new();
+            let mut collisions = Collisions(Vec::new());
+            state.simulate_with(&coast, &coast, &mut stats, &mut collisions);
+            assert!(!stats.collisions_abandoned);
+            let at_start = collisions.0.iter().filter(|&&(t, c)| t == 0. && c == Collision::Entity(0, 2)).count();
+            assert_eq!(at_start, 1, "{:?}", collisions.0);
+        }
+
+        // Compared bit for bit, a -0 that should have been 0 is a difference too.
+        fn assert_rounds_to(x: f64, expected: f64) {
+            let rounded = Rounding::HalfUp.apply(x);
+            assert!(rounded.to_bits() == expected.to_bits(), "{:?} rounded to {:?} instead of {:?}", x, rounded, expected);
+        }
+
+        #[test]
+        fn halves_round_up() {
+            assert_rounds_to(2.5, 3.);
+            assert_rounds_to(-2.5, -2.);
+            assert_rounds_to(-0.5, 0.);
+        }
+
+        #[test]
+        fn just_below_a_half_rounds_down() {
+            assert_rounds_to(0.49999999999999994, 0.);
+            assert_rounds_to(-0.49999999999999994, 0.);
+        }
+
+        #[test]
+        fn zero_and_nan_become_zero() {
+            assert_rounds_to(-0., 0.);
+            assert_rounds_to(0., 0.);
+            assert_rounds_to(f64::NAN, 0.);
+        }
+
+        // Nothing left to round from 2^52 on, and past what an i64 holds it saturates.
+        #[test]
+        fn large_values_stay_whole() {
+            assert_rounds_to(4503599627370497., 4503599627370497.);
+            assert_rounds_to(-9007199254740994., -9007199254740994.);
+            assert_rounds_to(4503599627370495.5, 4503599627370496.);
+            assert_rounds_to(1e300, 9223372036854775808.);
+            assert_rounds_to(f64::NEG_INFINITY, -9223372036854775808.);
+        }
+
+        // A speed of 2 ends up at 1.5 after friction, for wizards and snaffles alike.
+        #[test]
+        fn halves_after_friction() {
+            assert_eq!(2. * WIZARD_FRICTION, 1.5);
+            assert_rounds_to(2. * WIZARD_FRICTION, 2.);
+            assert_rounds_to(-2. * WIZARD_FRICTION, -1.);
+            assert_rounds_to(-2. * SNAFFLE_FRICTION, -1.);
+        }
+
+        #[test]
+        #[cfg(feature = "prng")]
+        fn simulate_rounds_negative_halves_up() {
+            let mut state = TurnState::generate(0);
+            state.entities[0].vel = Vec2(-2., 2.);
+            let pos = state.entities[0].pos;
+            let coast = [Action::Move(Vec2(8000., 3750.), 0); WIZARD_COUNT];
+            state.simulate(&coast, &coast);
+            assert!(state.entities[0].pos == pos + Vec2(-2., 2.));
+            assert!(state.entities[0].vel == Vec2(-1., 2.), "velocity is {:?}", state.entities[0].vel);
+        }
+
+        // The collision scheduler may only skip work, never change what happens.
+        #[test]
+        #[cfg(feature = "prng")]
+        fn scheduled_simulation_matches_reference() {
+            for seed in 0..3000 {
+                let mut rng = Prng::new(seed ^ 0xabcdef);
+                let mut state = TurnState::generate(seed);
+                while state.winner.is_none() {
+                    let my_actions = random_actions(&mut rng, &state);
+                    let enemy_actions = random_actions(&mut rng, &state);
+                    let mut scheduled = state;
+                    let scheduled_stats = scheduled.simulate(&my_actions, &enemy_actions);
+                    let mut reference = state;
+                    let reference_stats = reference.simulate_reference(&my_actions, &enemy_actions);
+                    assert_eq!(&scheduled.entities[..], &reference.entities[..], "game {}, round {}", seed, state.round);
+                    assert_eq!(scheduled_stats, reference_stats, "game {}, round {}", seed, state.round);
+                    assert_eq!((scheduled.my_score, scheduled.enemy_score, scheduled.winner), (reference.my_score, reference.enemy_score, reference.winner));
+                    state = scheduled;
+                }
+            }
+        }
+    }
+}
+// Advances a whole population of games in lockstep, e.g. every candidate of a search starting
+// from the same root. The games go LANES at a time, with every position and velocity kept in
+// arrays indexed by entity then game for the whole turn, so each stage does the same arithmetic
+// over a row of games and the compiler can vectorize it. That goes for the collisions too: a row
+// keeps looking for the next one until every game in it is through the turn. Every game ends up
+// exactly where TurnState::simulate would have put it, which the tests check bit for bit.
+#[allow(dead_code)] mod batch {
+    use std::f64;
+    use std::ops::Range;
+    use std::u8;
+    use std::u32;
+    use consts::*;
+    use game::*;
+    use vec2::Vec2;
+
+    // Games stepped together. A row steps as often as its busiest game needs to, so more lanes
+    // also means more wasted work in the collision checks.
+    const LANES: usize = 8;
+    const NO_LINK: u8 = u8::MAX;
+    // Collisions are numbered, walls first, then poles, then pairs of entities.
+    const NO_COLLISION: u32 = u32::MAX;
+    const POLE_COLLISIONS: u32 = 2 * MAX_ENTITY_COUNT as u32;
+    const ENTITY_COLLISIONS: u32 = POLE_COLLISIONS + 4 * MAX_ENTITY_COUNT as u32;
+
+    type Lanes<T> = [T; LANES];
+
+    // LANES games, entity by entity. Lanes past the end of the batch count as games that have ended.
+    #[derive(Copy, Clone)]
+    struct Chunk {
+        pos_x: [Lanes<f64>; MAX_ENTITY_COUNT],
+        pos_y: [Lanes<f64>; MAX_ENTITY_COUNT],
+        vel_x: [Lanes<f64>; MAX_ENTITY_COUNT],
+        vel_y: [Lanes<f64>; MAX_ENTITY_COUNT],
+        linked: [Lanes<u8>; MAX_ENTITY_COUNT],
+        snaffle_timeout: [Lanes<i64>; MAX_ENTITY_COUNT],
+        dead: [Lanes<bool>; MAX_ENTITY_COUNT],
+        round: Lanes<usize>,
+        winner: Lanes<Option<Faction>>,
+        my_mp: Lanes<i64>,
+        enemy_mp: Lanes<i64>,
+        my_score: Lanes<i64>,
+        enemy_score: Lanes<i64>,
+        spells: Lanes<SpellList>
+    }
+
+    impl Chunk {
+        #[inline]
+        fn pos(&self, e: EntityId, g: usize) -> Vec2 {
+            Vec2(self.pos_x[e][g], self.pos_y[e][g])
+        }
+
+        #[inline]
+        fn vel(&self, e: EntityId, g: usize) -> Vec2 {
+            Vec2(self.vel_x[e][g], self.vel_y[e][g])
+        }
+
+        #[inline]
+        fn set_pos(&mut self, e: EntityId, g: usize, pos: Vec2) {
+            self.pos_x[e][g] = pos.0;
+            self.pos_y[e][g] = pos.1;
+        }
+
+        #[inline]
+        fn set_vel(&mut self, e: EntityId, g: usize, vel: Vec2) {
+            self.vel_x[e][g] = vel.0;
+            self.vel_y[e][g] = vel.1;
+        }
+
+        #[inline]
+        fn linked(&self, e: EntityId, g: usize) -> Option<EntityId> {
+            match self.linked[e][g] {
+                NO_LINK => None,
+                id      => Some(id as EntityId)
+            }
+        }
+    }
+
+    // Only needed while a chunk is being stepped, shared by all of them.
+    struct Scratch {
+        running: Lanes<bool>,
+        actions: [[Option<Action>; TOTAL_WIZARD_COUNT]; LANES],
+        target_x: [Lanes<f64>; MAX_ENTITY_COUNT],
+        target_y: [Lanes<f64>; MAX_ENTITY_COUNT],
+        power: [Lanes<f64>; MAX_ENTITY_COUNT],
+        thrusting: [Lanes<bool>; MAX_ENTITY_COUNT],
+        collected: Vec<Vec<(EntityId, EntityId)>>,
+        // The rest is the state of the movement loop of simulate, for every lane.
+        t: Lanes<f64>,
+        steps: Lanes<usize>,
+        abandoned: Lanes<bool>,
+        moving: Lanes<bool>,
+        searching: Lanes<bool>,
+        earliest: Lanes<f64>,
+        collision: Lanes<u32>,
+        last_collision: Lanes<u32>,
+        last_collision_t: Lanes<f64>,
+        border_bounds: [Lanes<f64>; MAX_ENTITY_COUNT],
+        pair_bounds: [[Lanes<f64>; MAX_ENTITY_COUNT]; MAX_ENTITY_COUNT],
+        // The smallest of pair_bounds[i], so all the pairs of i can be skipped at once.
+        row_bounds: [Lanes<f64>; MAX_ENTITY_COUNT]
+    }
+
+    pub struct Batch {
+        // What all the games have in common: entity kinds, factions and sub ids.
+        template: TurnState,
+        len: usize,
+        chunks: Vec<Chunk>,
+        scratch: Box<Scratch>
+    }
+
+    impl Batch {
+        pub fn new(root: &TurnState, count: usize) -> Batch {
+            Batch::from_states(&vec![*root; count])
+        }
+
+        // The games all need the same entities in the same order, only their values can differ.
+        pub fn from_states(states: &[TurnState]) -> Batch {
+            let mut template = states.first().map_or(TurnState::init(TOTAL_WIZARD_COUNT + BLUDGER_COUNT), |s| *s);
+            for state in states.iter() {
+                if state.entities.len() != template.entities.len() || state.my_id != template.my_id {
+                    panic!("Every game of a batch needs the same entities and the same player!");
+                }
+            }
+            // Whether an entity is dead is up to each game, check_action shouldn't see the first one's.
+            for e in template.entities.iter_mut() {
+                e.dead = false;
+            }
+            let chunk_count = (states.len() + LANES - 1) / LANES;
+            let mut chunks = vec![Chunk {
+                pos_x: [[0.; LANES]; MAX_ENTITY_COUNT],
+                pos_y: [[0.; LANES]; MAX_ENTITY_COUNT],
+                vel_x: [[0.; LANES]; MAX_ENTITY_COUNT],
+                vel_y: [[0.; LANES]; MAX_ENTITY_COUNT],
+                linked: [[NO_LINK; LANES]; MAX_ENTITY_COUNT],
+                snaffle_timeout: [[0; LANES]; MAX_ENTITY_COUNT],
+                dead: [[true; LANES]; MAX_ENTITY_COUNT],
+                round: [0; LANES],
+                winner: [Some(Faction::Neutral); LANES],
+                my_mp: [0; LANES],
+                enemy_mp: [0; LANES],
+                my_score: [0; LANES],
+                enemy_score: [0; LANES],
+                spells: [SpellList::new(); LANES]
+            }; chunk_count];
+            for (i, state) in states.iter().enumerate() {
+                let (chunk, g) = (&mut chunks[i / LANES], i % LANES);
+                for (e, entity) in state.entities.iter().enumerate() {
+                    chunk.set_pos(e, g, entity.pos);
+                    chunk.set_vel(e, g, entity.vel);
+                    chunk.linked[e][g] = entity.linked.map_or(NO_LINK, |id| id as u8);
+                    chunk.snaffle_timeout[e][g] = entity.snaffle_timeout;
+                    chunk.dead[e][g] = entity.dead;
+                }
+                chunk.round[g] = state.round;
+                chunk.winner[g] = state.winner;
+                chunk.my_mp[g] = state.my_mp;
+                chunk.enemy_mp[g] = state.enemy_mp;
+                chunk.my_score[g] = state.my_score;
+                chunk.enemy_score[g] = state.enemy_score;
+                chunk.spells[g] = state.active_spells;
+            }
+            Batch {
+                template: template,
+                len: states.len(),
+                chunks: chunks,
+                scratch: Box::new(Scratch {
+                    running: [false; LANES],
+                    actions: [[None; TOTAL_WIZARD_COUNT]; LANES],
+                    target_x: [[0.; LANES]; MAX_ENTITY_COUNT],
+                    target_y: [[0.; LANES]; MAX_ENTITY_COUNT],
+                    power: [[0.; LANES]; MAX_ENTITY_COUNT],
+                    thrusting: [[false; LANES]; MAX_ENTITY_COUNT],
+                    collected: vec![Vec::new(); LANES],
+                    t: [0.; LANES],
+                    steps: [0; LANES],
+                    abandoned: [false; LANES],
+                    moving: [false; LANES],
+                    searching: [false; LANES],
+                    earliest: [0.; LANES],
+                    collision: [NO_COLLISION; LANES],
+                    last_collision: [NO_COLLISION; LANES],
+                    last_collision_t: [0.; LANES],
+                    border_bounds: [[0.; LANES]; MAX_ENTITY_COUNT],
+                    pair_bounds: [[[0.; LANES]; MAX_ENTITY_COUNT]; MAX_ENTITY_COUNT],
+                    row_bounds: [[0.; LANES]; MAX_ENTITY_COUNT]
+                })
+            }
+        }
+
+        #[inline]
+        pub fn len(&self) -> usize {
+            self.len
+        }
+
+        #[inline]
+        pub fn winner(&self, i: usize) -> Option<Faction> {
+            self.chunks[i / LANES].winner[i % LANES]
+        }
+
+        pub fn is_over(&self) -> bool {
+            (0..self.len).all(|i| self.winner(i).is_some())
+        }
+
+        // Game i as a regular TurnState.
+        pub fn state(&self, i: usize) -> TurnState {
+            if i >= self.len { panic!("Index out of bounds!"); }
+            let (chunk, g) = (&self.chunks[i / LANES], i % LANES);
+            let mut state = self.template;
+            for (e, entity) in state.entities.iter_mut().enumerate() {
+                entity.pos = chunk.pos(e, g);
+                entity.vel = chunk.vel(e, g);
+                entity.linked = chunk.linked(e, g);
+                entity.snaffle_timeout = chunk.snaffle_timeout[e][g];
+                entity.dead = chunk.dead[e][g];
+            }
+            state.round = chunk.round[g];
+            state.winner = chunk.winner[g];
+            state.my_mp = chunk.my_mp[g];
+            state.enemy_mp = chunk.enemy_mp[g];
+            state.my_score = chunk.my_score[g];
+            state.enemy_score = chunk.enemy_score[g];
+            state.active_spells = chunk.spells[g];
+            state
+        }
+
+        pub fn states(&self) -> Vec<TurnState> {
+            (0..self.len).map(|i| self.state(i)).collect()
+        }
+
+        // Plays one turn of every game that hasn't ended yet, with `my_actions[i]` and `enemy_actions[i]`
+        // as the actions for game i. The games that have ended are left alone instead of panicking like
+        // simulate does, there's no stats either.
+        pub fn step<A: AsRef<[Action]>>(&mut self, my_actions: &[A], enemy_actions: &[A]) {
+            if my_actions.len() != self.len || enemy_actions.len() != self.len {
+                panic!("Expected actions for {} games, got {} and {}!", self.len, my_actions.len(), enemy_actions.len());
+            }
+            for (c, chunk) in self.chunks.iter_mut().enumerate() {
+                let games = c * LANES..self.len.min((c + 1) * LANES);
+                step_chunk(chunk, &mut self.scratch, &self.template, &my_actions[games.clone()], &enemy_actions[games]);
+            }
+        }
+    }
+
+    // The same stages as TurnState::simulate, see there for what each of them does.
+    fn step_chunk<A: AsRef<[Action]>>(chunk: &mut Chunk, s: &mut Scratch, template: &TurnState, my_actions: &[A], enemy_actions: &[A]) {
+        let n = template.entities.len();
+        for g in 0..LANES {
+            s.running[g] = chunk.winner[g].is_none();
+        }
+        if !s.running.iter().any(|&r| r) {
+            return;
+        }
+        // Snaffle timeouts.
+        for i in 0..TOTAL_WIZARD_COUNT {
+            for g in 0..LANES {
+                chunk.snaffle_timeout[i][g] -= s.running[g] as i64;
+            }
+        }
+        // Stage 1: Thrust
+        for e in 0..n {
+            s.thrusting[e] = [false; LANES];
+        }
+        for g in 0..LANES {
+            if !s.running[g] {
+                continue;
+            }
+            for i in 0..TOTAL_WIZARD_COUNT {
+                let wizard = &template.entities[i];
+                let action = if wizard.aligned_with(Faction::Me) { my_actions[g].as_ref()[wizard.sub_id] }
+                                                             else { enemy_actions[g].as_ref()[wizard.sub_id] };
+                // The template has everything alive, so that's the only thing left to check.
+                s.actions[g][i] = match action {
+                    Action::Spell(_, eid) if eid < n && chunk.dead[eid][g] => None,
+                    _                                                      => template.check_action(i, action)
+                };
+                let (tg, power) = match s.actions[g][i] {
+                    Some(Action::Move(tg, thrust)) => {
+                        set_thrust(s, i, g, tg, thrust as f64);
+                        (Vec2::zero(), 0)
+                    },
+                    Some(Action::Throw(tg, power)) => (tg, power),
+                    _                              => (Vec2::zero(), 0)
+                };
+                // Thrusts only get applied at the end of the stage, so the snaffle still gets the velocity
+                // from before the wizard's.
+                if let Some(sid) = chunk.linked(i, g) {
+                    chunk.linked[i][g] = NO_LINK;
+                    let (pos, vel) = (chunk.pos(i, g), chunk.vel(i, g));
+                    chunk.set_pos(sid, g, pos);
+                    chunk.set_vel(sid, g, vel);
+                    chunk.linked[sid][g] = NO_LINK;
+                    set_thrust(s, sid, g, tg, power as f64);
+                }
+            }
+            for bid in template.bludger_ids() {
+                let mut closest = None;
+                for wid in 0..TOTAL_WIZARD_COUNT {
+                    if chunk.linked(bid, g) == Some(wid) {
+                        continue;
+                    }
+                    if is_obliviated(chunk, template, g, bid, template.entities[wid].faction) {
+                        continue;
+                    }
+                    let dist = chunk.pos(wid, g).distance_to(chunk.pos(bid, g));
+                    closest = Some(match closest {
+                        Some((_, best_dist)) if dist < best_dist => (wid, dist),
+                        Some(x) => x,
+                        None => (wid, dist)
+                    });
+                }
+                if let Some((best_wid, _)) = closest {
+                    let tg = chunk.pos(best_wid, g);
+                    set_thrust(s, bid, g, tg, 1000.);
+                }
+            }
+        }
+        for e in 0..n {
+            let inverse_mass = 1. / template.entities[e].kind.mass();
+            for g in 0..LANES {
+                // Same operations in the same order as Entity::apply_thrust_towards, or the results would drift apart.
+                let dx = s.target_x[e][g] - chunk.pos_x[e][g];
+                let dy = s.target_y[e][g] - chunk.pos_y[e][g];
+                let scale = 1.0 / (dx * dx + dy * dy).sqrt();
+                let new_x = chunk.vel_x[e][g] + dx * scale * s.power[e][g] * inverse_mass;
+                let new_y = chunk.vel_y[e][g] + dy * scale * s.power[e][g] * inverse_mass;
+                // Not even a +0 for the ones without a thrust, that turns -0 into 0.
+                chunk.vel_x[e][g] = if s.thrusting[e][g] { new_x } else { chunk.vel_x[e][g] };
+                chunk.vel_y[e][g] = if s.thrusting[e][g] { new_y } else { chunk.vel_y[e][g] };
+            }
+        }
+        // Stage 2: Spells
+        for g in 0..LANES {
+            if !s.running[g] {
+                continue;
+            }
+            let mut spells = chunk.spells[g];
+            for sp in spells.iter_mut() {
+                let mass = template.entities[sp.target_id].kind.mass();
+                match sp.kind {
+                    SpellType::Flipendo => {
+                        let towards = chunk.pos(sp.target_id, g) - chunk.pos(sp.caster_id, g);
+                        let dist = towards.len();
+                        if dist == 0. {
+                            continue;
+                        }
+                        let dir = towards.norm();
+                        let mut power = 6000. / ( dist / 1000. ).powi(2);
+                        if power > 1000. {
+                            power = 1000.;
+                        }
+                        let vel = chunk.vel(sp.target_id, g) + dir * power * (1. / mass);
+                        chunk.set_vel(sp.target_id, g, vel);
+                    },
+                    SpellType::Accio => {
+                        let towards = chunk.pos(sp.target_id, g) - chunk.pos(sp.caster_id, g);
+                        let dist = towards.len();
+                        if dist == 0. {
+                            sp.casted = 0;
+                            continue;
+                        }
+                        let dir = towards.norm();
+                        let mut power = 3000. / ( dist / 1000. ).powi(2);
+                        if power > 1000. {
+                            power = 1000.;
+                        }
+                        let vel = chunk.vel(sp.target_id, g) + -dir * power * (1. / mass);
+                        chunk.set_vel(sp.target_id, g, vel);
+                    },
+                    SpellType::Petrificus => chunk.set_vel(sp.target_id, g, Vec2::zero()),
+                    SpellType::Obliviate => ()
+                }
+            }
+            let round = chunk.round[g];
+            spells.retain(|sp| round - sp.casted < sp.duration);
+            chunk.spells[g] = spells;
+        }
+        // Stage 3: Movement
+        move_entities(chunk, s, template);
+        // Stage 4: Friction
+        for e in 0..n {
+            let friction = template.entities[e].kind.friction();
+            for g in 0..LANES {
+                chunk.vel_x[e][g] = if s.running[g] { chunk.vel_x[e][g] * friction } else { chunk.vel_x[e][g] };
+                chunk.vel_y[e][g] = if s.running[g] { chunk.vel_y[e][g] * friction } else { chunk.vel_y[e][g] };
+            }
+        }
+        // Stage 5: Rounding
+        for e in 0..n {
+            for g in 0..LANES {
+                chunk.vel_x[e][g] = if s.running[g] { VELOCITY_ROUNDING.apply(chunk.vel_x[e][g]) } else { chunk.vel_x[e][g] };
+                chunk.vel_y[e][g] = if s.running[g] { VELOCITY_ROUNDING.apply(chunk.vel_y[e][g]) } else { chunk.vel_y[e][g] };
+                chunk.pos_x[e][g] = if s.running[g] { POSITION_ROUNDING.apply(chunk.pos_x[e][g]) } else { chunk.pos_x[e][g] };
+                chunk.pos_y[e][g] = if s.running[g] { POSITION_ROUNDING.apply(chunk.pos_y[e][g]) } else { chunk.pos_y[e][g] };
+            }
+        }
+        for g in 0..LANES {
+            if s.running[g] {
+                end_turn(chunk, s, template, g);
+            }
+        }
+    }
+
+    #[inline]
+    fn set_thrust(s: &mut Scratch, e: EntityId, g: usize, tg: Vec2, power: f64) {
+        s.target_x[e][g] = tg.0;
+        s.target_y[e][g] = tg.1;
+        s.power[e][g] = power;
+        s.thrusting[e][g] = true;
+    }
+
+    fn is_obliviated(chunk: &Chunk, template: &TurnState, g: usize, bid: EntityId, faction: Faction) -> bool {
+        chunk.spells[g].iter().any(|sp| sp.kind == SpellType::Obliviate
+                                     && sp.target_id == bid
+                                     && template.entities[sp.caster_id].aligned_with(faction))
+    }
+
+    // Every lane looks for its next collision at once, with the bounds of simulate deciding which
+    // checks a lane needs. A check gets skipped only when none of the lanes need it.
+    fn move_entities(chunk: &mut Chunk, s: &mut Scratch, template: &TurnState) {
+        let n = template.entities.len();
+        for g in 0..LANES {
+            s.t[g] = 0.;
+            s.steps[g] = 0;
+            s.abandoned[g] = false;
+            s.last_collision[g] = NO_COLLISION;
+            s.last_collision_t[g] = 0.;
+            s.collected[g].clear();
+        }
+        for i in 0..n {
+            let rad_i = template.entities[i].kind.radius();
+            for g in 0..LANES {
+                s.border_bounds[i][g] = border_collision_bound(chunk.pos_x[i][g], chunk.pos_y[i][g], chunk.vel_x[i][g], chunk.vel_y[i][g], rad_i);
+            }
+            for j in (i + 1)..n {
+                let rad_j = template.entities[j].kind.radius();
+                for g in 0..LANES {
+                    s.pair_bounds[i][j][g] = pair_collision_bound(chunk.pos_x[j][g] - chunk.pos_x[i][g], chunk.pos_y[j][g] - chunk.pos_y[i][g],
+                                                                  chunk.vel_x[j][g] - chunk.vel_x[i][g], chunk.vel_y[j][g] - chunk.vel_y[i][g], rad_i, rad_j);
+                }
+            }
+            for g in 0..LANES {
+                s.row_bounds[i][g] = row_bound(&s.pair_bounds[i], i + 1..n, g);
+            }
+        }
+        loop {
+            for g in 0..LANES {
+                s.moving[g] = s.running[g] && s.t[g] < 1.;
+                if s.moving[g] {
+                    s.steps[g] += 1;
+                    if s.steps[g] > MAX_COLLISION_STEPS {
+                        s.abandoned[g] = true;
+                    }
+                }
+                s.searching[g] = s.moving[g] && !s.abandoned[g];
+                // Nothing can come before minus infinity, that keeps the other lanes out of the checks.
+                s.earliest[g] = if s.searching[g] { 1. - s.t[g] } else { f64::NEG_INFINITY };
+                s.collision[g] = NO_COLLISION;
+            }
+            if !s.moving.iter().any(|&m| m) {
+                break;
+            }
+            for i in 0..n {
+                let kind_i = template.entities[i].kind;
+                let rad_i = kind_i.radius();
+                let snaffle = kind_i == EntityType::Snaffle;
+                let mut needed = [false; LANES];
+                for g in 0..LANES {
+                    needed[g] = s.searching[g] & !chunk.dead[i][g] & (s.border_bounds[i][g] - s.t[g] <= s.earliest[g]);
+                }
+                // Working out the times and picking the earliest are separate loops, so the first one is only
+                // arithmetic.
+                let mut col_t = [0.; LANES];
+                let mut which = [0; LANES];
+                if needed.iter().any(|&x| x) {
+                    for g in 0..LANES {
+                        let (t, is_vertical) = rebound_time(chunk.pos_x[i][g], chunk.pos_y[i][g], chunk.vel_x[i][g], chunk.vel_y[i][g], rad_i, snaffle);
+                        col_t[g] = t;
+                        which[g] = is_vertical as u32;
+                    }
+                    for g in 0..LANES {
+                        consider(s, g, needed[g], col_t[g], 2 * i as u32 + which[g]);
+                    }
+                    for g in 0..LANES {
+                        let (t, pid) = pole_rebound_time(chunk.pos_x[i][g], chunk.pos_y[i][g], chunk.vel_x[i][g], chunk.vel_y[i][g], rad_i);
+                        col_t[g] = t;
+                        which[g] = pid;
+                    }
+                    for g in 0..LANES {
+                        consider(s, g, needed[g], col_t[g], POLE_COLLISIONS + 4 * i as u32 + which[g]);
+                    }
+                }
+                // Subtracting t keeps the order, so none of the pairs can pass if their smallest bound doesn't.
+                let mut any = false;
+                for g in 0..LANES {
+                    any |= s.searching[g] & !chunk.dead[i][g] & !(s.row_bounds[i][g] - s.t[g] > s.earliest[g]);
+                }
+                if !any {
+                    continue;
+                }
+                for j in (i + 1)..n {
+                    let kind_j = template.entities[j].kind;
+                    let pickup = kind_i == EntityType::Wizard && kind_j == EntityType::Snaffle;
+                    let radius_sum = if pickup { rad_i - 1. } else { rad_i + kind_j.radius() };
+                    // & rather than &&, so checking a lane doesn't branch.
+                    let mut any = false;
+                    for g in 0..LANES {
+                        needed[g] = s.searching[g] & !chunk.dead[i][g] & !chunk.dead[j][g] & !(s.pair_bounds[i][j][g] - s.t[g] > s.earliest[g]);
+                        if pickup {
+                            needed[g] = needed[g] & (chunk.snaffle_timeout[i][g] <= 0) & (chunk.linked[j][g] == NO_LINK);
+                        }
+                        any |= needed[g];
+                    }
+                    if !any {
+                        continue;
+                    }
+                    let code = ENTITY_COLLISIONS + (i * MAX_ENTITY_COUNT + j) as u32;
+                    for g in 0..LANES {
+                        col_t[g] = circle_collision_time(chunk.pos_x[j][g] - chunk.pos_x[i][g], chunk.pos_y[j][g] - chunk.pos_y[i][g],
+                                                         chunk.vel_x[j][g] - chunk.vel_x[i][g], chunk.vel_y[j][g] - chunk.vel_y[i][g], radius_sum);
+                    }
+                    for g in 0..LANES {
+                        consider(s, g, needed[g], col_t[g], code);
+                    }
+                }
+            }
+            for g in 0..LANES {
+                s.earliest[g] = if !s.moving[g] { 0. } else if s.abandoned[g] { 1. - s.t[g] } else { s.earliest[g] };
+            }
+            for e in 0..n {
+                for g in 0..LANES {
+                    let dt = s.earliest[g];
+                    chunk.pos_x[e][g] = if dt > 0. { chunk.pos_x[e][g] + chunk.vel_x[e][g] * dt } else { chunk.pos_x[e][g] };
+                    chunk.pos_y[e][g] = if dt > 0. { chunk.pos_y[e][g] + chunk.vel_y[e][g] * dt } else { chunk.pos_y[e][g] };
+                }
+            }
+            for g in 0..LANES {
+                if !s.moving[g] {
+                    continue;
+                }
+                s.t[g] += s.earliest[g];
+                if s.collision[g] != NO_COLLISION {
+                    s.last_collision[g] = s.collision[g];
+                    s.last_collision_t[g] = s.t[g];
+                    collide(chunk, s, template, g);
+                }
+            }
+        }
+    }
+
+    // Takes the collision if it's the earliest so far, like the checks in the movement loop of simulate.
+    #[inline(always)]
+    fn consider(s: &mut Scratch, g: usize, needed: bool, col_t: f64, code: u32) {
+        let repeated = (col_t == 0.) & (s.last_collision[g] == code) & (s.last_collision_t[g] == s.t[g]);
+        let take = needed & (col_t < s.earliest[g]) & !repeated;
+        s.earliest[g] = if take { col_t } else { s.earliest[g] };
+        s.collision[g] = if take { code } else { s.collision[g] };
+    }
+
+    // The response to the collision lane g just got to, then the bounds it changed.
+    fn collide(chunk: &mut Chunk, s: &mut Scratch, template: &TurnState, g: usize) {
+        let n = template.entities.len();
+        let code = s.collision[g];
+        let (a, b) = if code < POLE_COLLISIONS {
+            let i = (code / 2) as usize;
+            let vel = chunk.vel(i, g);
+            if code % 2 == 1 {
+                chunk.set_vel(i, g, Vec2(vel.0, -vel.1));
+            }
+            else {
+                chunk.set_vel(i, g, Vec2(-vel.0, vel.1));
+            }
+            (i, i)
+        }
+        else if code < ENTITY_COLLISIONS {
+            let i = ((code - POLE_COLLISIONS) / 4) as usize;
+            let pid = ((code - POLE_COLLISIONS) % 4) as usize;
+            let radius_sum = POLE_RADIUS + template.entities[i].kind.radius();
+            let dp = POLE_POS[pid] - chunk.pos(i, g);
+            let dv = -chunk.vel(i, g);
+            let raw_impulse = dp * (dp * dv) * (1. / radius_sum.powi(2));
+            let mut impulse = if raw_impulse.len() < 100. { raw_impulse.norm() * 100. } else { raw_impulse };
+            if impulse.is_nan() {
+                impulse = Vec2(0., 0.);
+            }
+            let vel = chunk.vel(i, g) + raw_impulse + impulse;
+            chunk.set_vel(i, g, vel);
+            (i, i)
+        }
+        else {
+            let i = ((code - ENTITY_COLLISIONS) as usize) / MAX_ENTITY_COUNT;
+            let j = ((code - ENTITY_COLLISIONS) as usize) % MAX_ENTITY_COUNT;
+            entity_collision(chunk, s, template, g, i, j);
+            (i, j)
+        };
+        let t = s.t[g];
+        // Rebounds off something the entity is already past come out negative, and the bounds
+        // only hold going forward.
+        if s.earliest[g] < 0. {
+            for i in 0..n {
+                s.border_bounds[i][g] = t + lane_border_bound(chunk, template, g, i);
+                for j in (i + 1)..n {
+                    s.pair_bounds[i][j][g] = t + lane_pair_bound(chunk, template, g, i, j);
+                }
+            }
+        }
+        else {
+            s.border_bounds[a][g] = t + lane_border_bound(chunk, template, g, a);
+            s.border_bounds[b][g] = t + lane_border_bound(chunk, template, g, b);
+            for k in 0..n {
+                for &changed in &[a, b] {
+                    if k != changed {
+                        let (i, j) = if k < changed { (k, changed) } else { (changed, k) };
+                        s.pair_bounds[i][j][g] = t + lane_pair_bound(chunk, template, g, i, j);
+                    }
+                }
+            }
+        }
+        for i in 0..n {
+            s.row_bounds[i][g] = row_bound(&s.pair_bounds[i], i + 1..n, g);
+        }
+    }
+
+    // The smallest bound in `bounds`, but a NaN anywhere makes it NaN, like it would fail the check on its own.
+    #[inline(always)]
+    fn row_bound(bounds: &[Lanes<f64>; MAX_ENTITY_COUNT], js: Range<EntityId>, g: usize) -> f64 {
+        let mut smallest = f64::INFINITY;
+        for j in js {
+            smallest = if bounds[j][g] < smallest || bounds[j][g].is_nan() { bounds[j][g] } else { smallest };
+        }
+        smallest
+    }
+
+    // Same as TurnState::collision_response.
+    fn entity_collision(chunk: &mut Chunk, s: &mut Scratch, template: &TurnState, g: usize, i: EntityId, j: EntityId) {
+        let (kind_i, kind_j) = (template.entities[i].kind, template.entities[j].kind);
+        if kind_i == EntityType::Wizard {
+            if kind_j == EntityType::Bludger {
+                chunk.linked[j][g] = i as u8;
+            }
+            else if kind_j == EntityType::Snaffle {
+                chunk.linked[i][g] = j as u8;
+                chunk.linked[j][g] = i as u8;
+                chunk.snaffle_timeout[i][g] = 3;
+                s.collected[g].push((i, j));
+                return;
+            }
+        }
+        let mass_i = kind_i.mass();
+        let mass_j = kind_j.mass();
+        let radius_sum = kind_i.radius() + kind_j.radius();
+        let dp = chunk.pos(j, g) - chunk.pos(i, g);
+        let dv = chunk.vel(j, g) - chunk.vel(i, g);
+        let raw_impulse = dp * (dp * dv) * mass_i * mass_j * (1. / (mass_i + mass_j) / radius_sum.powi(2));
+        let mut impulse = if raw_impulse.len() < 100. { raw_impulse.norm() * 100. } else { raw_impulse };
+        if impulse.is_nan() {
+            impulse = Vec2(0., 0.);
+        }
+        let vel_i = chunk.vel(i, g) + raw_impulse * (1. / mass_i) + impulse * (1. / mass_i);
+        let vel_j = chunk.vel(j, g) - raw_impulse * (1. / mass_j) - impulse * (1. / mass_j);
+        chunk.set_vel(i, g, vel_i);
+        chunk.set_vel(j, g, vel_j);
+    }
+
+    fn lane_border_bound(chunk: &Chunk, template: &TurnState, g: usize, i: EntityId) -> f64 {
+        border_collision_bound(chunk.pos_x[i][g], chunk.pos_y[i][g], chunk.vel_x[i][g], chunk.vel_y[i][g], template.entities[i].kind.radius())
+    }
+
+    fn lane_pair_bound(chunk: &Chunk, template: &TurnState, g: usize, i: EntityId, j: EntityId) -> f64 {
+        pair_collision_bound(chunk.pos_x[j][g] - chunk.pos_x[i][g], chunk.pos_y[j][g] - chunk.pos_y[i][g],
+                             chunk.vel_x[j][g] - chunk.vel_x[i][g], chunk.vel_y[j][g] - chunk.vel_y[i][g],
+                             template.entities[i].kind.radius(), template.entities[j].kind.radius())
+    }
+
+    // Goals, pickups, casting and checking for a winner, one game at a time.
+    fn end_turn(chunk: &mut Chunk, s: &mut Scratch, template: &TurnState, g: usize) {
+        for sid in template.snaffle_ids() {
+            if chunk.dead[sid][g] {
+                continue;
+            }
+            let right = chunk.pos_x[sid][g] >= 16000.;
+            if right || chunk.pos_x[sid][g] <= 0. {
+                chunk.dead[sid][g] = true;
+                if right == (template.my_id == 0) {
+                    chunk.my_score[g] += 1;
+                }
+                else {
+                    chunk.enemy_score[g] += 1;
+                }
+            }
+        }
+        for &(wid, sid) in &s.collected[g] {
+            let (pos, vel) = (chunk.pos(wid, g), chunk.vel(wid, g));
+            chunk.set_pos(sid, g, pos);
+            chunk.set_vel(sid, g, vel);
+        }
+        for i in 0..TOTAL_WIZARD_COUNT {
+            if let Some(Action::Spell(kind, eid)) = s.actions[g][i] {
+                let mp = if template.entities[i].aligned_with(Faction::Me) { &mut chunk.my_mp[g] } else { &mut chunk.enemy_mp[g] };
+                if *mp >= kind.cost() {
+                    *mp -= kind.cost();
+                    // Same as TurnState::add_spell.
+                    let spell = Spell::new(kind, i, eid, chunk.round[g]);
+                    if kind == SpellType::Petrificus {
+                        chunk.spells[g].insert(0, spell);
+                    }
+                    else {
+                        chunk.spells[g].push(spell);
+                    }
+                }
+            }
+        }
+        let (my_score, enemy_score) = (chunk.my_score[g], chunk.enemy_score[g]);
+        if chunk.round[g] >= 200 {
+            chunk.winner[g] = Some(if my_score > enemy_score { Faction::Me } else if my_score < enemy_score { Faction::Enemy } else { Faction::Neutral });
+            return;
+        }
+        let snaffle_count = template.entities.len() - TOTAL_WIZARD_COUNT - BLUDGER_COUNT;
+        if my_score > snaffle_count as i64 / 2 {
+            chunk.winner[g] = Some(Faction::Me);
+            return;
+        }
+        if enemy_score > snaffle_count as i64 / 2 {
+            chunk.winner[g] = Some(Faction::Enemy);
+            return;
+        }
+        chunk.my_mp[g] = (chunk.my_mp[g] + 1).min(MAX_MP);
+        chunk.enemy_mp[g] = (chunk.enemy_mp[g] + 1).min(MAX_MP);
+        chunk.round[g] += 1;
+    }
+
+    // Below are the collision checks of TurnState for a single lane, without branches so a whole row
+    // of them can be vectorized. Whatever is left over from the cases that don't apply gets thrown
+    // away by a select, so they have to come out exactly like their TurnState counterparts otherwise.
+    // No collision is infinity, nothing ever gets taken at that time. The times they do find are
+    // never NaN or infinite, so that's the same as None.
+
+    // math::circle_collision_time.
+    #[inline(always)]
+    fn circle_collision_time(dpos_x: f64, dpos_y: f64, dvel_x: f64, dvel_y: f64, radius_sum: f64) -> f64 {
+        let b = 2. * (dvel_x * dpos_x + dvel_y * dpos_y);
+        let c = (dpos_x * dpos_x + dpos_y * dpos_y) - radius_sum * radius_sum;
+        let a = dvel_x * dvel_x + dvel_y * dvel_y;
+        let disc = b * b - 4. * a * c;
+        let aa = 2. * a;
+        let dist = disc.sqrt() / aa;
+        let offset = -b / aa;
+        let (t1, t2) = (offset - dist, offset + dist);
+        let solved = if t1 >= 0. && t2 >= 0. { if t1 > t2 { t2 } else { t1 } }
+                     else if t1 >= 0. { t1 }
+                     else { t2 };
+        let found = !(b >= 0.) && (c <= 0. || (!(disc < 0.) && (t1 >= 0. || t2 >= 0.)));
+        if !found { f64::INFINITY }
+        else if c <= 0. { 0. }
+        else { solved }
+    }
+
+    // TurnState::rebound_time.
+    #[inline(always)]
+    fn rebound_time(pos_x: f64, pos_y: f64, vel_x: f64, vel_y: f64, rad: f64, snaffle: bool) -> (f64, bool) {
+        let to_top = ((BORDER_TOP_Y + rad) - pos_y) / vel_y;
+        let to_bottom = ((BORDER_BOTTOM_Y - rad) - pos_y) / vel_y;
+        let to_left = ((BORDER_LEFT_X + rad) - pos_x) / vel_x;
+        let to_right = ((BORDER_RIGHT_X - rad) - pos_x) / vel_x;
+        let t_top = if vel_y < 0. { to_top } else { f64::INFINITY };
+        let t_bottom = if vel_y > 0. { to_bottom } else { f64::INFINITY };
+        let t_left = if vel_x < 0. { to_left } else { f64::INFINITY };
+        let t_right = if vel_x > 0. { to_right } else { f64::INFINITY };
+        let mut t = if t_bottom < t_top { t_bottom } else { t_top };
+        let mut is_vertical = !(t_left < t);
+        t = if t_left < t { t_left } else { t };
+        is_vertical = is_vertical & !(t_right < t);
+        t = if t_right < t { t_right } else { t };
+        let res_y = pos_y + t * vel_y;
+        let into_goal = snaffle & !is_vertical & (res_y > GOAL_Y - GOAL_RADIUS) & (res_y < GOAL_Y + GOAL_RADIUS);
+        (if into_goal { f64::INFINITY } else { t }, is_vertical)
+    }
+
+    // TurnState::pole_rebound_time, the first pole wins a tie.
+    #[inline(always)]
+    fn pole_rebound_time(pos_x: f64, pos_y: f64, vel_x: f64, vel_y: f64, rad: f64) -> (f64, u32) {
+        let radius_sum = POLE_RADIUS + rad;
+        let (mut best_t, mut best_pid) = (f64::INFINITY, 0);
+        for pid in 0..POLE_POS.len() {
+            let new_t = circle_collision_time(POLE_POS[pid].0 - pos_x, POLE_POS[pid].1 - pos_y, -vel_x, -vel_y, radius_sum);
+            best_pid = if new_t < best_t { pid as u32 } else { best_pid };
+            best_t = if new_t < best_t { new_t } else { best_t };
+        }
+        (best_t, best_pid)
+    }
+
+    // TurnState::border_collision_bound.
+    #[inline(always)]
+    fn border_collision_bound(pos_x: f64, pos_y: f64, vel_x: f64, vel_y: f64, rad: f64) -> f64 {
+        let mut gap = (pos_x - BORDER_LEFT_X).min(BORDER_RIGHT_X - pos_x)
+                          .min(pos_y - BORDER_TOP_Y).min(BORDER_BOTTOM_Y - pos_y) - rad;
+        for &pole in &POLE_POS {
+            let (dx, dy) = (pole.0 - pos_x, pole.1 - pos_y);
+            gap = gap.min((dx * dx + dy * dy).sqrt() - POLE_RADIUS - rad);
+        }
+        gap -= COLLISION_BOUND_MARGIN;
+        let speed = (vel_x * vel_x + vel_y * vel_y).sqrt();
+        let bound = if speed == 0. { f64::INFINITY } else { gap / speed };
+        if gap <= 0. { 0. } else { bound }
+    }
+
+    // TurnState::pair_collision_bound, from where b is and how fast it goes compared to a.
+    #[inline(always)]
+    fn pair_collision_bound(dpos_x: f64, dpos_y: f64, dvel_x: f64, dvel_y: f64, rad_a: f64, rad_b: f64) -> f64 {
+        let gap = (dpos_x * dpos_x + dpos_y * dpos_y).sqrt() - rad_a - rad_b - COLLISION_BOUND_MARGIN;
+        let speed = (dvel_x * dvel_x + dvel_y * dvel_y).sqrt();
+        let bound = if speed == 0. { f64::INFINITY } else { gap / speed };
+        if gap <= 0. { 0. } else { bound }
+    }
+
+    #[cfg(test)]
+    #[cfg(feature = "prng")]
+    mod tests {
+        use super::*;
+        use prng::Prng;
+
+        fn random_actions(rng: &mut Prng, state: &TurnState) -> Vec<Action> {
+            (0..WIZARD_COUNT).map(|_| match rng.below(6) {
+                0 => {
+                    let kind = [SpellType::Flipendo, SpellType::Accio, SpellType::Obliviate, SpellType::Petrificus][rng.below(4) as usize];
+                    Action::Spell(kind, rng.range_usize(0, state.entities.len()))
+                },
+                1 | 2 => Action::Throw(rng.vec2_in_rect(Vec2(-2000., -2000.), Vec2(18000., 9500.)), rng.range(0, MAX_THROW_POWER + 1)),
+                _ => Action::Move(rng.vec2_in_rect(Vec2(BORDER_LEFT_X, BORDER_TOP_Y), Vec2(BORDER_RIGHT_X, BORDER_BOTTOM_Y)), rng.range(0, MAX_MOVE_THRUST + 1))
+            }).collect()
+        }
+
+        // Steps the batch and every game that hasn't ended with simulate, then compares them all, the
+        // ended ones must stay as they were.
+        fn step_both(batch: &mut Batch, states: &mut [TurnState], my_actions: &[Vec<Action>], enemy_actions: &[Vec<Action>]) {
+            batch.step(my_actions, enemy_actions);
+            for (i, state) in states.iter_mut().enumerate() {
+                if state.winner.is_none() {
+                    state.simulate(&my_actions[i], &enemy_actions[i]);
+                }
+                let stepped = batch.state(i);
+                assert_eq!(&stepped.entities[..], &state.entities[..], "game {}, round {}", i, state.round);
+                assert_eq!((stepped.round, stepped.winner, stepped.my_mp, stepped.enemy_mp, stepped.my_score, stepped.enemy_score),
+                           (state.round, state.winner, state.my_mp, state.enemy_mp, state.my_score, state.enemy_score), "game {}", i);
+                assert_eq!(format!("{:?}", stepped.active_spells), format!("{:?}", state.active_spells), "game {}, round {}", i, state.round);
+            }
+        }
+
+        // Whole games, some of them ending long before the others, and not a multiple of LANES so the
+        // last chunk has lanes to spare.
+        #[test]
+        fn batch_matches_simulate() {
+            let mut rng = Prng::new(0xba7c4);
+            let mut states: Vec<TurnState> = (0..4 * LANES + 3).map(|_| TurnState::generate_with(&mut rng, 7)).collect();
+            let mut batch = Batch::from_states(&states);
+            while !batch.is_over() {
+                let my_actions: Vec<Vec<Action>> = states.iter().map(|s| random_actions(&mut rng, s)).collect();
+                let enemy_actions: Vec<Vec<Action>> = states.iter().map(|s| random_actions(&mut rng, s)).collect();
+                step_both(&mut batch, &mut states, &my_actions, &enemy_actions);
+            }
+            assert!(states.iter().all(|s| s.winner.is_some()));
+        }
+
+        // The games from the collision guard tests of simulate, next to regular ones: one lane gives up
+        // on collisions and another skips a repeated one while the rest of the row goes on as usual.
+        #[test]
+        fn stuck_games_match_simulate() {
+            let mut rng = Prng::new(0x57ac);
+            let mut states: Vec<TurnState> = (0..LANES).map(|_| TurnState::generate_with(&mut rng, 3)).collect();
+            let count = states[0].entities.len();
+            for (i, e) in states[0].entities.iter_mut().enumerate() {
+                let angle = i as f64 * 2. * ::std::f64::consts::PI / count as f64;
+                e.pos = Vec2(8000., 100.) + Vec2(angle.cos(), angle.sin()) * 10.;
+                e.vel = Vec2::zero();
+            }
+            states[1].entities[0].pos = Vec2(8000., 1000.);
+            states[1].entities[0].vel = Vec2(1000., 0.);
+            states[1].entities[2].pos = Vec2(8010., 1000.);
+            states[1].entities[2].vel = Vec2(-1000., 0.);
+            let mut stacked = states[0];
+            assert!(stacked.simulate(&[Action::Move(Vec2(8000., 3750.), 0); WIZARD_COUNT], &[Action::Move(Vec2(8000., 3750.), 0); WIZARD_COUNT]).collisions_abandoned);
+            let mut batch = Batch::from_states(&states);
+            let coast = vec![vec![Action::Move(Vec2(8000., 3750.), 0); WIZARD_COUNT]; LANES];
+            step_both(&mut batch, &mut states, &coast, &coast);
+            for _ in 0..20 {
+                let my_actions: Vec<Vec<Action>> = states.iter().map(|s| random_actions(&mut rng, s)).collect();
+                let enemy_actions: Vec<Vec<Action>> = states.iter().map(|s| random_actions(&mut rng, s)).collect();
+                step_both(&mut batch, &mut states, &my_actions, &enemy_actions);
+            }
+        }
+    }
 }
 mod ai { /* … super sekrit … */ }
@@ -0,0 +1,138 @@
+use vec2::*;
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+// PCG32 (XSH-RR). Small, fast and every (seed, stream) pair gives its own sequence,
+// so the same seed always replays the exact same game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Prng {
+    state: u64,
+    inc: u64
+}
+
+impl Prng {
+    pub fn new(seed: u64) -> Prng {
+        Prng::with_stream(seed, 0)
+    }
+
+    pub fn with_stream(seed: u64, stream: u64) -> Prng {
+        let mut rng = Prng {
+            state: 0,
+            inc: (stream << 1) | 1
+        };
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+        rng
+    }
+
+    // A new generator that doesn't overlap with this one, for handing out to a subsystem
+    // without making it depend on how many numbers everything else pulled.
+    pub fn child(&mut self) -> Prng {
+        let seed = self.next_u64();
+        let stream = self.next_u64();
+        Prng::with_stream(seed, stream)
+    }
+
+    #[inline]
+    fn step(&mut self) {
+        self.state = self.state.wrapping_mul(MULTIPLIER).wrapping_add(self.inc);
+    }
+
+    #[inline]
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.step();
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    #[inline]
+    pub fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+    }
+
+    // Uniform in [0, 1).
+    #[inline]
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1. / (1u64 << 53) as f64)
+    }
+
+    #[inline]
+    pub fn next_bool(&mut self) -> bool {
+        self.next_u32() & 1 == 1
+    }
+
+    // True with probability `p`.
+    #[inline]
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+
+    // Uniform in [0, bound), without the modulo bias.
+    pub fn below(&mut self, bound: u32) -> u32 {
+        if bound == 0 {
+            panic!("Cannot pick a number below 0!");
+        }
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let r = self.next_u32();
+            if r >= threshold {
+                return r % bound;
+            }
+        }
+    }
+
+    // Same as below, for bounds that don't fit in 32 bits.
+    pub fn below_u64(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            panic!("Cannot pick a number below 0!");
+        }
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let r = self.next_u64();
+            if r >= threshold {
+                return r % bound;
+            }
+        }
+    }
+
+    // Uniform in [lo, hi).
+    #[inline]
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        if hi <= lo {
+            panic!("Empty range {}..{}!", lo, hi);
+        }
+        // Can be more than i64::MAX, but always fits in a u64.
+        let span = hi.wrapping_sub(lo) as u64;
+        let offset = if span <= u32::max_value() as u64 { self.below(span as u32) as u64 }
+                                                      else { self.below_u64(span) };
+        lo.wrapping_add(offset as i64)
+    }
+
+    // Uniform in [lo, hi).
+    #[inline]
+    pub fn range_usize(&mut self, lo: usize, hi: usize) -> usize {
+        self.range(lo as i64, hi as i64) as usize
+    }
+
+    // Uniform in [lo, hi).
+    #[inline]
+    pub fn range_f64(&mut self, lo: f64, hi: f64) -> f64 {
+        lo + self.next_f64() * (hi - lo)
+    }
+
+    // Uniform inside the rectangle spanned by the two corners.
+    #[inline]
+    pub fn vec2_in_rect(&mut self, min: Vec2, max: Vec2) -> Vec2 {
+        Vec2(self.range_f64(min.0, max.0), self.range_f64(min.1, max.1))
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.range_usize(0, i + 1);
+            items.swap(i, j);
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::io::prelude::*;
+
+use vec2::*;
+use draw::{Canvas, FIELD_OFFSET, SCALING, VIEW_SIZE};
+use game::*;
+
+pub type Color = (u8, u8, u8, u8);
+
+// Keeps every frame as a bunch of SVG elements, with the field units as user units.
+pub struct Svg {
+    fill: Option<Color>,
+    stroke: Option<Color>,
+    stroke_width: f64,
+    frames: Vec<String>
+}
+
+impl Svg {
+    pub fn new() -> Svg {
+        Svg {
+            fill: Some((255, 255, 255, 255)),
+            stroke: Some((0, 0, 0, 255)),
+            stroke_width: 1.,
+            frames: Vec::new()
+        }
+    }
+
+    // The elements of each frame, without the surrounding <svg>.
+    pub fn frames(&self) -> &[String] {
+        &self.frames
+    }
+
+    // A standalone SVG document showing one frame.
+    pub fn document(&self, frame: usize) -> String {
+        let mut out = String::new();
+        write!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\" width=\"{:.0}\" height=\"{:.0}\">",
+               -FIELD_OFFSET.0, -FIELD_OFFSET.1, VIEW_SIZE.0, VIEW_SIZE.1, VIEW_SIZE.0 * SCALING, VIEW_SIZE.1 * SCALING).unwrap();
+        write!(out, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"black\"/>", -FIELD_OFFSET.0, -FIELD_OFFSET.1, VIEW_SIZE.0, VIEW_SIZE.1).unwrap();
+        if let Some(body) = self.frames.get(frame) {
+            out.push_str(body);
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+
+    pub fn write_last<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let last = if self.frames.is_empty() { 0 } else { self.frames.len() - 1 };
+        out.write_all(self.document(last).as_bytes())
+    }
+
+    fn style(&self) -> String {
+        let mut style = String::new();
+        match self.fill {
+            Some((r, g, b, a)) => write!(style, " fill=\"rgb({},{},{})\" fill-opacity=\"{:.3}\"", r, g, b, a as f64 / 255.).unwrap(),
+            None               => style.push_str(" fill=\"none\"")
+        }
+        match self.stroke {
+            Some((r, g, b, a)) => write!(style, " stroke=\"rgb({},{},{})\" stroke-opacity=\"{:.3}\" stroke-width=\"{}\"", r, g, b, a as f64 / 255., self.stroke_width / SCALING).unwrap(),
+            None               => style.push_str(" stroke=\"none\"")
+        }
+        style
+    }
+
+    fn push(&mut self, element: String) {
+        if self.frames.is_empty() {
+            self.frames.push(String::new());
+        }
+        self.frames.last_mut().unwrap().push_str(&element);
+    }
+}
+
+impl Canvas for Svg {
+    fn set_fill_color(&mut self, r: u8, g: u8, b: u8, a: u8) { self.fill = Some((r, g, b, a)); }
+    fn set_stroke_color(&mut self, r: u8, g: u8, b: u8, a: u8) { self.stroke = Some((r, g, b, a)); }
+    fn set_stroke_width(&mut self, width: f64) { self.stroke_width = width; }
+    fn no_fill(&mut self) { self.fill = None; }
+    fn no_stroke(&mut self) { self.stroke = None; }
+
+    fn circle(&mut self, pos: Vec2, radius: f64) {
+        let element = format!("<circle cx=\"{}\" cy=\"{}\" r=\"{}\"{}/>", pos.0, pos.1, radius, self.style());
+        self.push(element);
+    }
+
+    fn rect(&mut self, pos: Vec2, size: Vec2) {
+        let element = format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"{}/>", pos.0, pos.1, size.0, size.1, self.style());
+        self.push(element);
+    }
+
+    fn ellipse(&mut self, pos: Vec2, size: Vec2) {
+        let element = format!("<ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\"{}/>", pos.0, pos.1, size.0 / 2., size.1 / 2., self.style());
+        self.push(element);
+    }
+
+    fn line(&mut self, from: Vec2, to: Vec2) {
+        let element = format!("<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"{}/>", from.0, from.1, to.0, to.1, self.style());
+        self.push(element);
+    }
+
+    fn text(&mut self, pos: Vec2, size: f64, text: &str) {
+        let fill = match self.fill {
+            Some((r, g, b, a)) => format!(" fill=\"rgb({},{},{})\" fill-opacity=\"{:.3}\"", r, g, b, a as f64 / 255.),
+            None               => return
+        };
+        let element = format!("<text x=\"{}\" y=\"{}\" font-size=\"{}\" font-family=\"monospace\"{}>{}</text>", pos.0, pos.1, size, fill, xml_escape(text));
+        self.push(element);
+    }
+
+    fn polyline(&mut self, points: &[Vec2]) {
+        let mut element = String::from("<polyline points=\"");
+        for p in points {
+            write!(element, "{},{} ", p.0, p.1).unwrap();
+        }
+        let fill = self.fill.take();
+        write!(element, "\"{}/>", self.style()).unwrap();
+        self.fill = fill;
+        self.push(element);
+    }
+
+    fn dashed_circle(&mut self, pos: Vec2, radius: f64, dash: f64) {
+        let fill = self.fill.take();
+        let element = format!("<circle cx=\"{}\" cy=\"{}\" r=\"{}\" stroke-dasharray=\"{}\"{}/>", pos.0, pos.1, radius, dash, self.style());
+        self.fill = fill;
+        self.push(element);
+    }
+
+    fn start_frame(&mut self) {
+        self.frames.push(String::new());
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c   => out.push(c)
+        }
+    }
+    out
+}
+
+// A plain RGB raster, written out as a binary PPM. Starting a frame clears it.
+pub struct Ppm {
+    width: usize,
+    height: usize,
+    scale: f64,
+    pixels: Vec<u8>,
+    fill: Option<Color>,
+    stroke: Option<Color>,
+    stroke_width: f64
+}
+
+impl Ppm {
+    pub fn new(width: usize, height: usize) -> Ppm {
+        Ppm {
+            width: width,
+            height: height,
+            scale: (width as f64 / VIEW_SIZE.0).min(height as f64 / VIEW_SIZE.1),
+            pixels: vec![0; width * height * 3],
+            fill: Some((255, 255, 255, 255)),
+            stroke: Some((0, 0, 0, 255)),
+            stroke_width: 1.
+        }
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.pixels)
+    }
+
+    #[inline]
+    fn to_px(&self, pos: Vec2) -> Vec2 {
+        (pos + FIELD_OFFSET) * self.scale
+    }
+
+    fn blend(&mut self, x: usize, y: usize, (r, g, b, a): Color) {
+        let i = (y * self.width + x) * 3;
+        let alpha = a as f64 / 255.;
+        for (k, &c) in [r, g, b].iter().enumerate() {
+            let old = self.pixels[i + k] as f64;
+            self.pixels[i + k] = (old + (c as f64 - old) * alpha).round() as u8;
+        }
+    }
+
+    // Blends `color` into every pixel in the box whose center satisfies `inside`.
+    fn paint<F: Fn(Vec2) -> bool>(&mut self, min: Vec2, max: Vec2, color: Color, inside: F) {
+        let x0 = min.0.floor().max(0.) as usize;
+        let y0 = min.1.floor().max(0.) as usize;
+        let x1 = (max.0.ceil().max(0.) as usize).min(self.width);
+        let y1 = (max.1.ceil().max(0.) as usize).min(self.height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                if inside(Vec2(x as f64 + 0.5, y as f64 + 0.5)) {
+                    self.blend(x, y, color);
+                }
+            }
+        }
+    }
+
+    // Shapes described by a signed distance in pixels, negative inside.
+    fn shape<F: Fn(Vec2) -> f64>(&mut self, min: Vec2, max: Vec2, dist: F) {
+        let half = self.stroke_width / 2.;
+        if let Some(color) = self.fill {
+            self.paint(min, max, color, |p| dist(p) <= 0.);
+        }
+        if let Some(color) = self.stroke {
+            self.paint(min - Vec2::same(half), max + Vec2::same(half), color, |p| dist(p).abs() <= half);
+        }
+    }
+}
+
+impl Canvas for Ppm {
+    fn set_fill_color(&mut self, r: u8, g: u8, b: u8, a: u8) { self.fill = Some((r, g, b, a)); }
+    fn set_stroke_color(&mut self, r: u8, g: u8, b: u8, a: u8) { self.stroke = Some((r, g, b, a)); }
+    fn set_stroke_width(&mut self, width: f64) { self.stroke_width = width; }
+    fn no_fill(&mut self) { self.fill = None; }
+    fn no_stroke(&mut self) { self.stroke = None; }
+
+    fn circle(&mut self, pos: Vec2, radius: f64) {
+        let center = self.to_px(pos);
+        let r = radius * self.scale;
+        self.shape(center - Vec2::same(r), center + Vec2::same(r), |p| p.distance_to(center) - r);
+    }
+
+    fn rect(&mut self, pos: Vec2, size: Vec2) {
+        let min = self.to_px(pos);
+        let max = min + size * self.scale;
+        self.shape(min, max, |p| (min.0 - p.0).max(p.0 - max.0).max(min.1 - p.1).max(p.1 - max.1));
+    }
+
+    fn ellipse(&mut self, pos: Vec2, size: Vec2) {
+        let center = self.to_px(pos);
+        let radii = size * (self.scale / 2.);
+        let smallest = radii.0.min(radii.1);
+        // Not an exact distance, but close enough for outlines a few pixels wide.
+        self.shape(center - radii, center + radii, |p| {
+            let d = p - center;
+            ((d.0 / radii.0).powi(2) + (d.1 / radii.1).powi(2)).sqrt() * smallest - smallest
+        });
+    }
+
+    fn line(&mut self, from: Vec2, to: Vec2) {
+        let color = match self.stroke {
+            Some(color) => color,
+            None        => return
+        };
+        let a = self.to_px(from);
+        let b = self.to_px(to);
+        let half = (self.stroke_width / 2.).max(0.5);
+        let min = Vec2(a.0.min(b.0), a.1.min(b.1)) - Vec2::same(half);
+        let max = Vec2(a.0.max(b.0), a.1.max(b.1)) + Vec2::same(half);
+        let ab = b - a;
+        let len_sq = ab.len_sq();
+        self.paint(min, max, color, |p| {
+            let t = if len_sq > 0. { ((p - a) * ab / len_sq).max(0.).min(1.) } else { 0. };
+            p.distance_to(a + ab * t) <= half
+        });
+    }
+
+    // A 3x5 pixel font scaled up to `size`, only digits, letters and a bit of punctuation.
+    fn text(&mut self, pos: Vec2, size: f64, text: &str) {
+        let color = match self.fill {
+            Some(color) => color,
+            None        => return
+        };
+        let cell = size * self.scale / 5.;
+        let mut corner = self.to_px(pos) - Vec2(0., size * self.scale);
+        for c in text.chars() {
+            let rows = glyph(c);
+            for (y, row) in rows.iter().enumerate() {
+                for x in 0..3 {
+                    if row & (0b100 >> x) != 0 {
+                        let min = corner + Vec2(x as f64, y as f64) * cell;
+                        self.paint(min, min + Vec2::same(cell), color, |_| true);
+                    }
+                }
+            }
+            corner.0 += 4. * cell;
+        }
+    }
+
+    fn start_frame(&mut self) {
+        for px in self.pixels.iter_mut() {
+            *px = 0;
+        }
+    }
+}
+
+// Rows of a 3x5 glyph, top first, leftmost pixel in the highest bit. Lowercase is drawn as uppercase.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        _   => [0; 5]
+    }
+}
+
+// One HTML file with every turn as an SVG frame and a slider to go through them.
+pub fn write_html_replay<W: Write>(out: &mut W, states: &[TurnState]) -> io::Result<()> {
+    let mut svg = Svg::new();
+    for state in states {
+        svg.start_frame();
+        state.draw(&mut svg);
+    }
+    let frames: Vec<String> = (0..states.len()).map(|i| js_string(&svg.document(i))).collect();
+    let infos: Vec<String> = states.iter().map(|s| js_string(&status(s))).collect();
+    write!(out, "{}", HTML_HEAD)?;
+    writeln!(out, "<script>")?;
+    writeln!(out, "var frames = [{}];", frames.join(",\n"))?;
+    writeln!(out, "var infos = [{}];", infos.join(",\n"))?;
+    write!(out, "{}", HTML_SCRIPT)?;
+    writeln!(out, "</script>\n</body>\n</html>")
+}
+
+fn status(state: &TurnState) -> String {
+    let mut out = String::new();
+    writeln!(out, "Round {}", state.round).unwrap();
+    writeln!(out, "Score {} - {}", state.my_score, state.enemy_score).unwrap();
+    writeln!(out, "MP    {} - {}", state.my_mp, state.enemy_mp).unwrap();
+    for s in state.active_spells.iter() {
+        writeln!(out, "{:?} from {} on {}, cast on round {}", s.kind, s.caster_id, s.target_id, s.casted).unwrap();
+    }
+    match state.winner {
+        Some(Faction::Me)      => writeln!(out, "Player 0 wins").unwrap(),
+        Some(Faction::Enemy)   => writeln!(out, "Player 1 wins").unwrap(),
+        Some(Faction::Neutral) => writeln!(out, "Draw").unwrap(),
+        None                   => ()
+    }
+    out
+}
+
+fn js_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"'  => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '/'  => out.push_str("\\/"), // So "</script>" can't end up in there.
+            c    => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+const HTML_HEAD: &'static str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Fantastic Bits replay</title>
+<style>
+body { background: #222; color: #eee; font-family: monospace; }
+#controls { margin: 8px 0; }
+#turn { width: 600px; vertical-align: middle; }
+</style>
+</head>
+<body>
+<div id="field"></div>
+<div id="controls">
+<button id="prev">&lt;</button>
+<button id="play">Play</button>
+<button id="next">&gt;</button>
+<input type="range" id="turn" min="0" value="0">
+</div>
+<pre id="info"></pre>
+"#;
+
+const HTML_SCRIPT: &'static str = r#"var slider = document.getElementById("turn");
+var playButton = document.getElementById("play");
+var timer = null;
+slider.max = frames.length - 1;
+function show(i) {
+    i = Math.max(0, Math.min(frames.length - 1, i));
+    slider.value = i;
+    document.getElementById("field").innerHTML = frames[i];
+    document.getElementById("info").textContent = infos[i];
+}
+function stop() {
+    clearInterval(timer);
+    timer = null;
+    playButton.textContent = "Play";
+}
+slider.oninput = function() { show(+slider.value); };
+document.getElementById("prev").onclick = function() { stop(); show(+slider.value - 1); };
+document.getElementById("next").onclick = function() { stop(); show(+slider.value + 1); };
+playButton.onclick = function() {
+    if (timer !== null) { stop(); return; }
+    if (+slider.value >= frames.length - 1) { show(0); }
+    playButton.textContent = "Pause";
+    timer = setInterval(function() {
+        if (+slider.value >= frames.length - 1) { stop(); return; }
+        show(+slider.value + 1);
+    }, 150);
+};
+document.onkeydown = function(e) {
+    if (e.key === "ArrowLeft") { stop(); show(+slider.value - 1); }
+    if (e.key === "ArrowRight") { stop(); show(+slider.value + 1); }
+};
+show(0);
+"#;
@@ -0,0 +1,171 @@
+use std::env;
+
+use game::*;
+use prng::Prng;
+
+// Whatever the tweaker is allowed to touch. Parameter names are the field names of the
+// config so the result can be pasted straight back into the source.
+//
+// AIConfig's weights live in the ai module, which isn't part of this tree, so main tunes
+// Chaser below until AIConfig gets an impl of its own in there.
+pub trait Tunable: Clone {
+    const NAME: &'static str;
+    fn param_names(&self) -> Vec<&'static str>;
+    fn params(&self) -> Vec<f64>;
+    fn set_params(&mut self, params: &[f64]);
+    fn decide(&mut self, state: &TurnState) -> Vec<Action>;
+}
+
+// Runs after the closest snaffle and throws it at the goal, about the simplest bot there is.
+#[derive(Clone, Debug)]
+pub struct Chaser {
+    // How many turns ahead of a moving snaffle to aim.
+    pub lead: f64,
+    pub thrust: f64,
+    pub throw_power: f64
+}
+
+impl Chaser {
+    pub fn new() -> Chaser {
+        Chaser {
+            lead: 1.,
+            thrust: 150.,
+            throw_power: 500.
+        }
+    }
+}
+
+impl Tunable for Chaser {
+    const NAME: &'static str = "Chaser";
+
+    fn param_names(&self) -> Vec<&'static str> {
+        vec!["lead", "thrust", "throw_power"]
+    }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.lead, self.thrust, self.throw_power]
+    }
+
+    fn set_params(&mut self, params: &[f64]) {
+        self.lead = params[0];
+        self.thrust = params[1];
+        self.throw_power = params[2];
+    }
+
+    // Out of range thrusts are fine, simulate clamps them.
+    fn decide(&mut self, state: &TurnState) -> Vec<Action> {
+        state.my_wizards().iter().map(|w| {
+            if w.linked.is_some() {
+                return Action::Throw(state.enemy_goal.center, self.throw_power.round() as i64);
+            }
+            let closest = state.snaffles().iter().filter(|s| !s.dead).min_by(|a, b| {
+                a.pos.distance_to(w.pos).partial_cmp(&b.pos.distance_to(w.pos)).unwrap()
+            });
+            let tg = closest.map_or(state.enemy_goal.center, |s| s.pos + s.vel * self.lead);
+            Action::Move(tg, self.thrust.round() as i64)
+        }).collect()
+    }
+}
+
+// SPSA constants, all in units of each parameter's initial magnitude.
+const PERTURBATION: f64 = 0.1;
+const STEP: f64 = 0.02;
+const ALPHA: f64 = 0.602;
+const GAMMA: f64 = 0.101;
+
+// Usage: tweaker [iterations] [games per evaluation]
+// Needs the prng feature as well.
+pub fn main() {
+    let mut args = env::args().skip(1);
+    let iterations = args.next().map_or(100, |s| s.parse::<usize>().expect("Invalid iteration count!"));
+    let games = args.next().map_or(20, |s| s.parse::<usize>().expect("Invalid game count!"));
+
+    let baseline = Chaser::new();
+    let mut rng = Prng::new(0x5eed);
+    let tuned = tune(&baseline, iterations, games, &mut rng);
+
+    let score = evaluate(&baseline, &tuned, games * 2, rng.next_u64());
+    println!("// Final score against the baseline: {:.3}", score);
+    println!("{} {{", Chaser::NAME);
+    for (name, value) in baseline.param_names().iter().zip(tuned.iter()) {
+        println!("    {}: {:?},", name, value);
+    }
+    println!("}}");
+}
+
+// SPSA starting from the parameters of `baseline`, every evaluation plays against it.
+pub fn tune<T: Tunable>(baseline: &T, iterations: usize, games: usize, rng: &mut Prng) -> Vec<f64> {
+    let initial = baseline.params();
+    let scales: Vec<f64> = initial.iter().map(|p| if p.abs() > 1e-9 { p.abs() } else { 1. }).collect();
+    let mut current = initial.clone();
+
+    let stability = iterations as f64 / 10.;
+    for k in 0..iterations {
+        let a_k = STEP / (k as f64 + 1. + stability).powf(ALPHA);
+        let c_k = PERTURBATION / (k as f64 + 1.).powf(GAMMA);
+        let delta: Vec<f64> = (0..current.len()).map(|_| if rng.next_bool() { 1. } else { -1. }).collect();
+        let plus: Vec<f64> = (0..current.len()).map(|i| current[i] + c_k * delta[i] * scales[i]).collect();
+        let minus: Vec<f64> = (0..current.len()).map(|i| current[i] - c_k * delta[i] * scales[i]).collect();
+
+        // Same seed for both sides so they get to play the exact same games.
+        let seed = rng.next_u64();
+        let score_plus = evaluate(baseline, &plus, games, seed);
+        let score_minus = evaluate(baseline, &minus, games, seed);
+
+        for i in 0..current.len() {
+            let gradient = (score_plus - score_minus) / (2. * c_k * delta[i]);
+            current[i] += a_k * gradient * scales[i];
+        }
+        println!("// Iteration {}: {:.3} vs {:.3}", k, score_plus, score_minus);
+    }
+    current
+}
+
+// Average score of `params` against `baseline`, 1 for a win and 0.5 for a draw.
+pub fn evaluate<T: Tunable>(baseline: &T, params: &[f64], games: usize, seed: u64) -> f64 {
+    let mut rng = Prng::new(seed);
+    let mut total = 0.;
+    for game in 0..games {
+        let mut ai = baseline.clone();
+        ai.set_params(params);
+        let mut opponent = baseline.clone();
+        let mut state = TurnState::generate(rng.next_u64());
+        // Play both sides of the field.
+        if game % 2 == 1 {
+            state.invert();
+        }
+        while state.winner.is_none() {
+            let my_actions = ai.decide(&state);
+            let mut enemy_view = state;
+            enemy_view.invert();
+            let enemy_actions = opponent.decide(&enemy_view);
+            state.simulate(&my_actions, &enemy_actions);
+        }
+        total += match state.winner {
+            Some(Faction::Me)    => 1.,
+            Some(Faction::Enemy) => 0.,
+            _                    => 0.5
+        };
+    }
+    total / games as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standing_still_loses() {
+        let score = evaluate(&Chaser::new(), &[1., 0., 0.], 4, 1);
+        assert_eq!(score, 0.);
+    }
+
+    #[test]
+    fn tuning_is_repeatable() {
+        let baseline = Chaser::new();
+        let tuned = tune(&baseline, 3, 2, &mut Prng::new(1));
+        assert_eq!(tuned.len(), baseline.params().len());
+        assert!(tuned.iter().all(|p| p.is_finite()));
+        assert_eq!(tuned, tune(&baseline, 3, 2, &mut Prng::new(1)));
+    }
+}